@@ -0,0 +1,27 @@
+//! Times the formatting of tables with more and more rows, which should grow linearly with their size.
+//! Run with `cargo bench`.
+
+use std::time::Instant;
+use mdfmt::{format_content, FormatOptions};
+
+fn tall_table(rows: usize) -> String {
+    let mut table = String::from("|id|name|value|\n|-|-|-:|\n");
+    for i in 0..rows {
+        table.push_str(&format!("|{}|row {}|{}|\n", i, i, i * 7));
+    }
+    table
+}
+
+fn main() {
+    let options = FormatOptions::default();
+    for &rows in &[1_000, 10_000, 100_000] {
+        let input = tall_table(rows);
+        let start = Instant::now();
+        let iterations = 1_000_000 / rows;
+        for _ in 0..iterations {
+            format_content(&input, &options).unwrap();
+        }
+        let elapsed = start.elapsed() / iterations as u32;
+        println!("{:>7} rows: {:>10.3?} per table, {:>8.1?} per row", rows, elapsed, elapsed / rows as u32);
+    }
+}
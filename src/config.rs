@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use mdfmt::{BlockquoteStyle, BulletChar, EmphasisChar, FinalNewline, FormatOptions, HeadingStyle, IndentStyle, LineEnding, OrderedListStyle, PipeStyle, SortBy, ThematicBreak};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+
+pub const CONFIG_FILE_NAME: &str = ".mdfmt.toml";
+
+/// Finds and loads the nearest configuration file of each formatted file, loading each configuration only once
+#[derive(Default)]
+pub struct Configs {
+    loaded: HashMap<PathBuf, FormatOptions>,
+    /// The options of the configuration file given with --config, used for every file instead of the nearest one
+    explicit: Option<FormatOptions>,
+}
+
+impl Configs {
+    /// Loads the configuration file at `path` for every formatted file, none are searched for
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Ok(Configs {
+            loaded: HashMap::new(),
+            explicit: Some(load_config(path)?),
+        })
+    }
+
+    /// Returns the options of the nearest configuration file in the directory of `path` (or the current directory) or its parents,
+    /// or the defaults if there is none
+    pub fn options_for(&mut self, path: Option<&Path>) -> Result<FormatOptions, Box<dyn Error>> {
+        if let Some(options) = &self.explicit {
+            return Ok(options.clone());
+        }
+        let current_dir = std::env::current_dir()?;
+        let dir = match path {
+            Some(path) => current_dir.join(path).parent().map_or_else(|| current_dir.clone(), Path::to_owned),
+            None => current_dir,
+        };
+        let config_path = match find_config(&dir) {
+            Some(config_path) => config_path,
+            None => return Ok(FormatOptions::default()),
+        };
+        if let Some(options) = self.loaded.get(&config_path) {
+            return Ok(options.clone());
+        }
+        let options = load_config(&config_path)?;
+        self.loaded.insert(config_path, options.clone());
+        Ok(options)
+    }
+}
+
+fn find_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+fn load_config(path: &Path) -> Result<FormatOptions, Box<dyn Error>> {
+    let content = read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let config = toml::from_str::<ConfigFile>(&content).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(config.apply(FormatOptions::default()))
+}
+
+/// The settings of a configuration file, named after the fields of `FormatOptions`, which are left as they are when unset.
+/// The options that take a value or `"preserve"` are given the value itself, like `bullet_char = "*"`.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    strict: Option<bool>,
+    lint_tables: Option<bool>,
+    warn_table_width: Option<usize>,
+    format_tables: Option<bool>,
+    collapse_cell_spaces: Option<bool>,
+    #[serde(default, deserialize_with = "from_str")]
+    line_ending: Option<LineEnding>,
+    ensure_final_newline: Option<bool>,
+    #[serde(default, deserialize_with = "from_str")]
+    final_newline: Option<FinalNewline>,
+    tab_width: Option<usize>,
+    allow_loose_pipes: Option<bool>,
+    strict_cell_count: Option<bool>,
+    compact: Option<bool>,
+    max_col_width: Option<usize>,
+    min_col_width: Option<usize>,
+    trim_trailing_whitespace: Option<bool>,
+    normalize_link_definitions: Option<bool>,
+    lowercase_link_labels: Option<bool>,
+    renumber_footnotes: Option<bool>,
+    max_blank_lines: Option<usize>,
+    #[serde(default, deserialize_with = "from_str_or_preserve")]
+    bullet_char: Option<Option<BulletChar>>,
+    #[serde(default, deserialize_with = "from_str_or_preserve")]
+    thematic_break: Option<Option<ThematicBreak>>,
+    #[serde(default, deserialize_with = "from_str_or_preserve")]
+    emphasis_char: Option<Option<EmphasisChar>>,
+    #[serde(default, deserialize_with = "from_str_or_preserve")]
+    strong_char: Option<Option<EmphasisChar>>,
+    #[serde(default, deserialize_with = "from_str")]
+    ordered_list_style: Option<OrderedListStyle>,
+    atx_closed_headings: Option<bool>,
+    fix_heading_space: Option<bool>,
+    #[serde(default, deserialize_with = "from_str")]
+    heading_style: Option<HeadingStyle>,
+    #[serde(default, deserialize_with = "from_str")]
+    indent_style: Option<IndentStyle>,
+    #[serde(default, deserialize_with = "from_str")]
+    blockquote_style: Option<BlockquoteStyle>,
+    auto_number_align: Option<bool>,
+    align_decimal: Option<bool>,
+    fit_delimiter_width: Option<bool>,
+    keep_bom: Option<bool>,
+    justify_cells: Option<bool>,
+    ascii_safe: Option<bool>,
+    unicode_dashes: Option<bool>,
+    normalize_table_captions: Option<bool>,
+    #[serde(default, deserialize_with = "from_str")]
+    pipe_style: Option<PipeStyle>,
+    outer_pipes: Option<bool>,
+    #[serde(default, deserialize_with = "from_str")]
+    sort_by: Option<SortBy>,
+    print_width: Option<usize>,
+    normalize_code_fence: Option<usize>,
+}
+
+impl ConfigFile {
+    fn apply(self, mut options: FormatOptions) -> FormatOptions {
+        macro_rules! apply {
+            // The setters of optional settings take an Option, which is None to turn them off
+            ($($setting:ident),* ; optional $($optional:ident),*) => {
+                $(if let Some(value) = self.$setting {
+                    options = options.$setting(value);
+                })*
+                $(if let Some(value) = self.$optional {
+                    options = options.$optional(Some(value));
+                })*
+            };
+        }
+        apply!(strict, lint_tables, format_tables, collapse_cell_spaces, line_ending, ensure_final_newline, final_newline, tab_width,
+               allow_loose_pipes, strict_cell_count, compact, min_col_width, trim_trailing_whitespace, normalize_link_definitions,
+               lowercase_link_labels, renumber_footnotes, bullet_char, thematic_break, emphasis_char, strong_char, ordered_list_style,
+               atx_closed_headings, fix_heading_space, heading_style, indent_style, blockquote_style, auto_number_align, align_decimal,
+               fit_delimiter_width, keep_bom, justify_cells, ascii_safe, unicode_dashes, normalize_table_captions, pipe_style, outer_pipes;
+               optional warn_table_width, max_col_width, max_blank_lines, sort_by, print_width, normalize_code_fence);
+        options
+    }
+}
+
+/// Deserializes a setting written as a string, like `line_ending = "crlf"`
+fn from_str<'de, D: Deserializer<'de>, T: FromStr<Err = String>>(deserializer: D) -> Result<Option<T>, D::Error> {
+    String::deserialize(deserializer)?.parse().map(Some).map_err(de::Error::custom)
+}
+
+/// Deserializes a setting written as a string, which is None if it's `"preserve"`
+fn from_str_or_preserve<'de, D: Deserializer<'de>, T: FromStr<Err = String>>(deserializer: D) -> Result<Option<Option<T>>, D::Error> {
+    match String::deserialize(deserializer)?.as_str() {
+        "preserve" => Ok(Some(None)),
+        value => value.parse().map(|value| Some(Some(value))).map_err(|err| de::Error::custom(format!("{}, or preserve", err))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(config: &str) -> Result<FormatOptions, toml::de::Error> {
+        toml::from_str::<ConfigFile>(config).map(|config| config.apply(FormatOptions::default()))
+    }
+
+    #[test]
+    fn settings_change_the_output() {
+        let options = options("bullet_char = \"*\"\nline_ending = \"crlf\"\nmax_blank_lines = 1\n").unwrap();
+        let formatted = mdfmt::format_content("- a\n\n\n\n- b\n", &options).unwrap();
+        assert_eq!(formatted.content, "* a\r\n\r\n* b\r\n");
+    }
+
+    #[test]
+    fn preserve_keeps_markers() {
+        let options = options("bullet_char = \"preserve\"\n").unwrap();
+        let formatted = mdfmt::format_content("+ a\n* b\n", &options).unwrap();
+        assert_eq!(formatted.content, "+ a\n* b\n");
+    }
+
+    #[test]
+    fn invalid_settings_are_errors() {
+        assert!(options("bullet_char = \"x\"\n").unwrap_err().to_string().contains("'x' is not a bullet"));
+        assert!(options("tab_width = \"4\"\n").is_err());
+        assert!(options("unknown = true\n").unwrap_err().to_string().contains("unknown field `unknown`"));
+        assert!(options("strict = \n").is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use std::collections::{HashMap, HashSet};
+
+const CONTEXT_LINES: usize = 3;
+
+#[derive(Clone, Copy)]
+enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Returns a unified diff from `original` to `formatted`, or an empty string if they are identical
+pub fn unified_diff(original: &str, formatted: &str, label: &str) -> String {
+    let old_lines = original.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = formatted.split_inclusive('\n').collect::<Vec<_>>();
+    let edits = shortest_edit(&old_lines, &new_lines);
+
+    let mut output = String::new();
+    for (start, end) in hunk_ranges(&edits) {
+        if output.is_empty() {
+            output.push_str(&format!("--- {}\n+++ {}\n", label, label));
+        }
+        write_hunk(&mut output, &edits[start..end], &old_lines, &new_lines);
+    }
+    output
+}
+
+/// Myers' diff algorithm, returns the edits in order.
+///
+/// The lines that only appear on one side can never be part of the common lines, so they are set aside before diffing
+/// the others: most lines of a reformatted table are like this. What remains is diffed in linear space by splitting it
+/// on the middle of an edit path, since keeping every step of the search takes memory in the square of the distance.
+fn shortest_edit(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let mut ids = HashMap::new();
+    let old_ids = old.iter().map(|line| { let id = ids.len(); *ids.entry(*line).or_insert(id) }).collect::<Vec<_>>();
+    let new_ids = new.iter().map(|line| { let id = ids.len(); *ids.entry(*line).or_insert(id) }).collect::<Vec<_>>();
+    let old_set = old_ids.iter().collect::<HashSet<_>>();
+    let new_set = new_ids.iter().collect::<HashSet<_>>();
+    // The indices of the lines that are on both sides
+    let old_kept = (0..old.len()).filter(|&x| new_set.contains(&old_ids[x])).collect::<Vec<_>>();
+    let new_kept = (0..new.len()).filter(|&y| old_set.contains(&new_ids[y])).collect::<Vec<_>>();
+    let old_common = old_kept.iter().map(|&x| old_ids[x]).collect::<Vec<_>>();
+    let new_common = new_kept.iter().map(|&y| new_ids[y]).collect::<Vec<_>>();
+
+    let mut common_edits = Vec::new();
+    diff_range(&old_common, &new_common, (0, old_common.len()), (0, new_common.len()), &mut common_edits);
+
+    // Puts the lines that were set aside back in order, as deletions and insertions before the next common edit
+    let mut edits = Vec::with_capacity(old.len() + new.len());
+    let (mut x, mut y) = (0, 0);
+    for edit in common_edits {
+        let (old_end, new_end) = match edit {
+            Edit::Equal(x, y) => (old_kept[x], new_kept[y]),
+            Edit::Delete(x) => (old_kept[x], y),
+            Edit::Insert(y) => (x, new_kept[y]),
+        };
+        edits.extend((x..old_end).map(Edit::Delete));
+        edits.extend((y..new_end).map(Edit::Insert));
+        edits.push(match edit {
+            Edit::Equal(..) => Edit::Equal(old_end, new_end),
+            Edit::Delete(_) => Edit::Delete(old_end),
+            Edit::Insert(_) => Edit::Insert(new_end),
+        });
+        x = old_end + usize::from(!matches!(edit, Edit::Insert(_)));
+        y = new_end + usize::from(!matches!(edit, Edit::Delete(_)));
+    }
+    edits.extend((x..old.len()).map(Edit::Delete));
+    edits.extend((y..new.len()).map(Edit::Insert));
+    edits
+}
+
+/// Pushes the edits from `old[old_range]` to `new[new_range]`, after the common lines at their start and end
+fn diff_range(old: &[usize], new: &[usize], (mut old_start, mut old_end): (usize, usize), (mut new_start, mut new_end): (usize, usize), edits: &mut Vec<Edit>) {
+    let mut suffix = Vec::new();
+    while old_start < old_end && new_start < new_end && old[old_start] == new[new_start] {
+        edits.push(Edit::Equal(old_start, new_start));
+        old_start += 1;
+        new_start += 1;
+    }
+    while old_start < old_end && new_start < new_end && old[old_end - 1] == new[new_end - 1] {
+        old_end -= 1;
+        new_end -= 1;
+        suffix.push(Edit::Equal(old_end, new_end));
+    }
+
+    if old_start == old_end {
+        edits.extend((new_start..new_end).map(Edit::Insert));
+    } else if new_start == new_end {
+        edits.extend((old_start..old_end).map(Edit::Delete));
+    } else {
+        // The ends differ, so there are at least two edits and both halves are shorter
+        let (x, y, snake_x, snake_y) = middle_snake(&old[old_start..old_end], &new[new_start..new_end]);
+        let (x, y, snake_x, snake_y) = (old_start + x, new_start + y, old_start + snake_x, new_start + snake_y);
+        diff_range(old, new, (old_start, x), (new_start, y), edits);
+        edits.extend((x..snake_x).zip(y..snake_y).map(|(x, y)| Edit::Equal(x, y)));
+        diff_range(old, new, (snake_x, old_end), (snake_y, new_end), edits);
+    }
+    edits.extend(suffix.into_iter().rev());
+}
+
+/// Finds the snake in the middle of a shortest edit path from `old` to `new`, searching from both ends at once,
+/// and returns where it starts and ends
+fn middle_snake(old: &[usize], new: &[usize]) -> (usize, usize, usize, usize) {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let delta = n - m;
+    let max = (n + m + 1) / 2 + 1;
+    let offset = max + 1;
+    // The furthest x reached on each diagonal, from the start and from the end (where x counts from the end)
+    let mut forward = vec![0isize; 2 * offset as usize + 1];
+    let mut backward = vec![0isize; 2 * offset as usize + 1];
+    let index = |k: isize| (offset + k) as usize;
+
+    for d in 0..=max {
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && forward[index(k - 1)] < forward[index(k + 1)]) {
+                forward[index(k + 1)]
+            } else {
+                forward[index(k - 1)] + 1
+            };
+            let (start_x, start_y) = (x, x - k);
+            let mut y = start_y;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[index(k)] = x;
+            let backward_k = delta - k;
+            if delta % 2 != 0 && (-(d - 1)..=d - 1).contains(&backward_k) && x + backward[index(backward_k)] >= n {
+                return (start_x as usize, start_y as usize, x as usize, y as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && backward[index(k - 1)] < backward[index(k + 1)]) {
+                backward[index(k + 1)]
+            } else {
+                backward[index(k - 1)] + 1
+            };
+            let (end_x, end_y) = (x, x - k);
+            let mut y = end_y;
+            while x < n && y < m && old[(n - 1 - x) as usize] == new[(m - 1 - y) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[index(k)] = x;
+            let forward_k = delta - k;
+            if delta % 2 == 0 && (-d..=d).contains(&forward_k) && x + forward[index(forward_k)] >= n {
+                return ((n - x) as usize, (m - y) as usize, (n - end_x) as usize, (m - end_y) as usize);
+            }
+        }
+    }
+    unreachable!("the searches meet before the distance is larger than both sequences")
+}
+
+/// Groups the changed edits with their surrounding context, merging hunks whose context overlaps
+fn hunk_ranges(edits: &[Edit]) -> Vec<(usize, usize)> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for (i, edit) in edits.iter().enumerate() {
+        if let Edit::Equal(..) = edit {
+            continue;
+        }
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + 1 + CONTEXT_LINES).min(edits.len());
+        match ranges.last_mut() {
+            Some(last) if last.1 >= start => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+    ranges
+}
+
+fn write_hunk(output: &mut String, edits: &[Edit], old: &[&str], new: &[&str]) {
+    let old_len = edits.iter().filter(|e| !matches!(e, Edit::Insert(_))).count();
+    let new_len = edits.iter().filter(|e| !matches!(e, Edit::Delete(_))).count();
+    let old_start = edits.iter().find_map(|e| match *e {
+        Edit::Equal(x, _) | Edit::Delete(x) => Some(x),
+        Edit::Insert(_) => None,
+    }).unwrap_or(old.len());
+    let new_start = edits.iter().find_map(|e| match *e {
+        Edit::Equal(_, y) | Edit::Insert(y) => Some(y),
+        Edit::Delete(_) => None,
+    }).unwrap_or(new.len());
+    // An empty range starts at the line before it, per the unified format
+    let old_start = if old_len == 0 { old_start } else { old_start + 1 };
+    let new_start = if new_len == 0 { new_start } else { new_start + 1 };
+    output.push_str(&format!("@@ -{},{} +{},{} @@\n", old_start, old_len, new_start, new_len));
+
+    for edit in edits {
+        let (prefix, line) = match *edit {
+            Edit::Equal(x, _) => (' ', old[x]),
+            Edit::Delete(x) => ('-', old[x]),
+            Edit::Insert(y) => ('+', new[y]),
+        };
+        output.push(prefix);
+        output.push_str(line);
+        if !line.ends_with('\n') {
+            output.push_str("\n\\ No newline at end of file\n");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that `edits` go through both sides in order, and returns how many lines they change
+    fn check_edits(edits: &[Edit], old: &[&str], new: &[&str]) -> usize {
+        let (mut x, mut y) = (0, 0);
+        for edit in edits {
+            match *edit {
+                Edit::Equal(ex, ey) => {
+                    assert_eq!((ex, ey), (x, y));
+                    assert_eq!(old[x], new[y]);
+                    x += 1;
+                    y += 1;
+                },
+                Edit::Delete(ex) => {
+                    assert_eq!(ex, x);
+                    x += 1;
+                },
+                Edit::Insert(ey) => {
+                    assert_eq!(ey, y);
+                    y += 1;
+                },
+            }
+        }
+        assert_eq!((x, y), (old.len(), new.len()));
+        edits.iter().filter(|edit| !matches!(edit, Edit::Equal(..))).count()
+    }
+
+    /// The number of changed lines of a shortest diff, from the longest common subsequence
+    fn edit_distance(old: &[&str], new: &[&str]) -> usize {
+        let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for x in (0..old.len()).rev() {
+            for y in (0..new.len()).rev() {
+                lcs[x][y] = if old[x] == new[y] { lcs[x + 1][y + 1] + 1 } else { lcs[x + 1][y].max(lcs[x][y + 1]) };
+            }
+        }
+        old.len() + new.len() - 2 * lcs[0][0]
+    }
+
+    #[test]
+    fn unaligned_table() {
+        let diff = unified_diff("|a|b|\n|-|-|\n|1|2|\n", "| a | b |\n|---|---|\n| 1 | 2 |\n", "table.md");
+        assert_eq!(diff, "--- table.md\n+++ table.md\n@@ -1,3 +1,3 @@\n-|a|b|\n-|-|-|\n-|1|2|\n+| a | b |\n+|---|---|\n+| 1 | 2 |\n");
+    }
+
+    #[test]
+    fn table_between_text() {
+        let original = "Title\n\n|a|b|\n|-|-|\n\nEnd\n";
+        let formatted = "Title\n\n| a | b |\n|---|---|\n\nEnd\n";
+        assert_eq!(unified_diff(original, formatted, "t.md"),
+                   "--- t.md\n+++ t.md\n@@ -1,6 +1,6 @@\n Title\n \n-|a|b|\n-|-|-|\n+| a | b |\n+|---|---|\n \n End\n");
+        assert_eq!(unified_diff(original, original, "t.md"), "");
+    }
+
+    #[test]
+    fn missing_final_newline() {
+        assert_eq!(unified_diff("|a|", "| a |\n", "t.md"), "--- t.md\n+++ t.md\n@@ -1,1 +1,1 @@\n-|a|\n\\ No newline at end of file\n+| a |\n");
+    }
+
+    #[test]
+    fn shortest_diffs() {
+        // Small random documents made of a few repeated lines, where many diffs have the same length
+        let mut seed = 7u32;
+        let mut random_lines = |len: u32| (0..len).map(|_| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ["a\n", "b\n", "c\n", "\n"][(seed >> 16) as usize % 4]
+        }).collect::<Vec<_>>();
+        for len in 0..200 {
+            let old = random_lines(len % 23);
+            let new = random_lines(len % 17);
+            let edits = shortest_edit(&old, &new);
+            assert_eq!(check_edits(&edits, &old, &new), edit_distance(&old, &new), "{:?} -> {:?}", old, new);
+        }
+    }
+
+    #[test]
+    fn large_reformatted_table() {
+        let original = (0..12_000).map(|i| format!("|{}|row|\n\n", i)).collect::<String>();
+        let formatted = (0..12_000).map(|i| format!("| {} | row |\n\n", i)).collect::<String>();
+        let old = original.split_inclusive('\n').collect::<Vec<_>>();
+        let new = formatted.split_inclusive('\n').collect::<Vec<_>>();
+        assert_eq!(check_edits(&shortest_edit(&old, &new), &old, &new), 24_000);
+    }
+}
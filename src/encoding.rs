@@ -0,0 +1,87 @@
+use std::borrow::Cow;
+use std::io;
+use std::str::FromStr;
+use encoding_rs::{EncoderResult, UTF_8};
+
+/// The encoding of the files read and written, the formatting itself always happens in UTF-8
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Encoding(&'static encoding_rs::Encoding);
+
+impl Encoding {
+    pub const UTF_8: Encoding = Encoding(UTF_8);
+
+    /// Transcodes `input` to UTF-8, failing on bytes that aren't a character of this encoding.
+    /// UTF-8 is left as it is and checked when formatting it.
+    pub fn decode(self, input: &[u8]) -> io::Result<Cow<'_, [u8]>> {
+        if self.0 == UTF_8 {
+            return Ok(Cow::Borrowed(input));
+        }
+        match self.0.decode_without_bom_handling_and_without_replacement(input) {
+            Some(Cow::Borrowed(decoded)) => Ok(Cow::Borrowed(decoded.as_bytes())),
+            Some(Cow::Owned(decoded)) => Ok(Cow::Owned(decoded.into_bytes())),
+            None => Err(io::Error::new(io::ErrorKind::InvalidData, format!("The input is not valid {}", self.0.name()))),
+        }
+    }
+
+    /// Transcodes formatted `content` back to this encoding, failing on the first character it can't represent
+    pub fn encode(self, content: &str) -> io::Result<Cow<'_, [u8]>> {
+        if self.0 == UTF_8 {
+            return Ok(Cow::Borrowed(content.as_bytes()));
+        }
+        let mut encoder = self.0.new_encoder();
+        let mut encoded = Vec::with_capacity(encoder.max_buffer_length_from_utf8_without_replacement(content.len()).unwrap_or(content.len()));
+        match encoder.encode_from_utf8_to_vec_without_replacement(content, &mut encoded, true) {
+            (EncoderResult::InputEmpty, _) => Ok(Cow::Owned(encoded)),
+            (EncoderResult::Unmappable(c), _) => Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("The output contains '{}', which can't be written in {}", c, self.0.name()))),
+            (EncoderResult::OutputFull, _) => unreachable!("the output buffer grows as needed"),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    /// Parses a WHATWG encoding label like `utf-8`, `latin-1` or `shift_jis`.
+    /// Encodings where a `\n` byte isn't always a line break, like UTF-16, are not supported since files are split into lines before decoding.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `latin-1` is the usual spelling, but not one of the labels of ISO-8859-1
+        let label = if s.eq_ignore_ascii_case("latin-1") { "latin1" } else { s };
+        match encoding_rs::Encoding::for_label(label.as_bytes()) {
+            Some(encoding) if encoding.is_ascii_compatible() => Ok(Encoding(encoding)),
+            Some(encoding) => Err(format!("'{}' is not a supported encoding ({} is not ASCII compatible)", s, encoding.name())),
+            None => Err(format!("'{}' is not a supported encoding (expected a label like utf-8 or latin-1)", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latin1_table_round_trips() {
+        let encoding = "latin-1".parse::<Encoding>().unwrap();
+        let input = b"|caf\xe9|x|\n|-|-|\n|\xe0 la|\xff|\n";
+        let decoded = encoding.decode(input).unwrap();
+        let formatted = mdfmt::format_bytes(&decoded, &mdfmt::FormatOptions::default()).unwrap().content;
+        assert_eq!(formatted, "| café | x |\n|------|---|\n| à la | ÿ |\n");
+        assert_eq!(&encoding.encode(&formatted).unwrap()[..], &b"| caf\xe9 | x |\n|------|---|\n| \xe0 la | \xff |\n"[..]);
+    }
+
+    #[test]
+    fn unmappable_characters_are_errors() {
+        let encoding = "iso-8859-1".parse::<Encoding>().unwrap();
+        let err = encoding.encode("a → b").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("'→'"), "{}", err);
+        assert!("shift_jis".parse::<Encoding>().unwrap().decode(b"\x81\n").is_err());
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!("UTF8".parse::<Encoding>(), Ok(Encoding::UTF_8));
+        assert!("utf-16".parse::<Encoding>().unwrap_err().contains("not ASCII compatible"));
+        assert!("nope".parse::<Encoding>().is_err());
+    }
+}
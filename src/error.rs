@@ -0,0 +1,47 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Why a document couldn't be formatted.
+///
+/// Broken tables and other problems that still let the document be formatted are `Diagnostic`s instead.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FormatError {
+    /// The input isn't valid UTF-8, starting at this 1-based line and byte column
+    Encoding { line: usize, column: usize },
+    /// The range given to `format_range` starts or ends inside the fenced code block that starts at this 1-based line
+    SplitCodeBlock { line: usize },
+    /// The writer given to `format_to_writer` failed, with the kind and message of its `io::Error`
+    Io { kind: io::ErrorKind, message: String },
+}
+
+impl FormatError {
+    /// The error for `content`, which is valid UTF-8 up to `valid_up_to`, that starts at `first_line`
+    pub(crate) fn invalid_utf8(content: &[u8], valid_up_to: usize, first_line: usize) -> Self {
+        let valid = &content[..valid_up_to];
+        let line_start = valid.iter().rposition(|&b| b == b'\n').map_or(0, |newline| newline + 1);
+        FormatError::Encoding {
+            line: first_line + valid.iter().filter(|&&b| b == b'\n').count(),
+            column: 1 + valid_up_to - line_start,
+        }
+    }
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FormatError::Encoding { line, column } => write!(f, "{}:{}: The input is not valid UTF-8", line, column),
+            FormatError::SplitCodeBlock { line } => write!(f, "{}: The range splits the code block starting on this line", line),
+            FormatError::Io { message, .. } => write!(f, "The output couldn't be written: {}", message),
+        }
+    }
+}
+
+impl Error for FormatError {}
+
+impl From<io::Error> for FormatError {
+    fn from(err: io::Error) -> Self {
+        FormatError::Io { kind: err.kind(), message: err.to_string() }
+    }
+}
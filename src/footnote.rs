@@ -0,0 +1,94 @@
+use std::borrow::Cow;
+use crate::format::find_code_span_end;
+
+/// The numbers given to the footnotes with a numeric label like `[^3]`, the others keep their name
+#[derive(Clone, Default)]
+pub(crate) struct FootnoteNumbers {
+    /// The original labels, numbered from 1 in this order
+    labels: Vec<String>,
+    /// The original labels in the order of their first reference, which definitions aren't
+    referenced: Vec<String>,
+}
+
+impl FootnoteNumbers {
+    /// Numbers the footnotes in the order they were first referenced while these numbers were used,
+    /// the footnotes that are defined without being referenced get the next numbers when they are found
+    pub(crate) fn by_first_reference(self) -> Self {
+        FootnoteNumbers {
+            labels: self.referenced,
+            referenced: Vec::new(),
+        }
+    }
+
+    fn number(&mut self, label: &str) -> usize {
+        match self.labels.iter().position(|numbered| numbered == label) {
+            Some(index) => index + 1,
+            None => {
+                self.labels.push(label.to_owned());
+                self.labels.len()
+            },
+        }
+    }
+}
+
+/// Returns the length of the `[^label]:` that starts a footnote definition, including its indentation
+pub(crate) fn footnote_definition_len(line: &str) -> Option<usize> {
+    let content = line.trim_start_matches(' ');
+    let indent = line.len() - content.len();
+    if indent > 3 {
+        return None;
+    }
+    let label_len = footnote_label_len(content)?;
+    content[label_len..].starts_with(':').then_some(indent + label_len + 1)
+}
+
+/// The length of the `[^label]` at the start of `text`, labels can't contain spaces or brackets
+fn footnote_label_len(text: &str) -> Option<usize> {
+    let label = text.strip_prefix("[^")?;
+    let end = label.find(|c: char| c == ']' || c == '[' || c.is_whitespace())?;
+    (end > 0 && label[end..].starts_with(']')).then_some(2 + end + 1)
+}
+
+/// Rewrites the numeric labels of the footnote references and definitions of `line` with their new numbers,
+/// the labels in code spans and escaped brackets are left as they are
+pub(crate) fn renumber_footnotes<'a>(line: &'a str, numbers: &mut FootnoteNumbers) -> Cow<'a, str> {
+    let definition_start = footnote_definition_len(line).map(|len| line[..len].find('[').unwrap());
+    let mut renumbered = String::new();
+    let mut copied = 0;
+    let mut i = 0;
+    while let Some(c) = line[i..].chars().next() {
+        match c {
+            '\\' => i += 1 + line[i+1..].chars().next().map_or(0, char::len_utf8),
+            '`' => {
+                let fence_len = line[i..].bytes().take_while(|&b| b == b'`').count();
+                i += find_code_span_end(&line[i+fence_len..], fence_len).map_or(fence_len, |end| fence_len + end);
+            },
+            '[' => match footnote_label_len(&line[i..]) {
+                Some(len) => {
+                    let label = &line[i+2..i+len-1];
+                    if label.bytes().all(|b| b.is_ascii_digit()) {
+                        if Some(i) != definition_start && !numbers.referenced.iter().any(|referenced| referenced == label) {
+                            numbers.referenced.push(label.to_owned());
+                        }
+                        renumbered.push_str(&line[copied..i]);
+                        renumbered.push_str(&format!("[^{}]", numbers.number(label)));
+                        copied = i + len;
+                    }
+                    i += len;
+                },
+                None => i += 1,
+            },
+            _ => i += c.len_utf8(),
+        }
+    }
+
+    if copied == 0 {
+        return Cow::Borrowed(line);
+    }
+    renumbered.push_str(&line[copied..]);
+    if renumbered == line {
+        Cow::Borrowed(line)
+    } else {
+        Cow::Owned(renumbered)
+    }
+}
@@ -1,29 +1,57 @@
-use std::error::Error;
-use unicode_width::UnicodeWidthStr;
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::error::FormatError;
+use crate::footnote::{footnote_definition_len, renumber_footnotes, FootnoteNumbers};
+use crate::html::HtmlBlock;
+use crate::options::{BlockquoteStyle, FinalNewline, FormatOptions, HeadingStyle, LineEnding, PipeStyle, SortComparator};
+use crate::width::{clusters, display_width};
+use crate::text::{is_atx_heading, is_table_caption, is_thematic_break, list_item_content_offset, normalize_link_definition, normalize_text_line, parse_blockquote, quote_markers, setext_underline, setext_underline_level, starts_plain_paragraph, wrap_paragraph, TextState};
 
-#[derive(Clone)]
-enum TableAlignment {
+/// How the cells of a column are aligned, as set by the colons of the delimiter row
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableAlignment {
     None,
     Left,
     Center,
     Right,
 }
 
-#[derive(Clone)]
-struct TableColumn {
-    alignment: TableAlignment,
-    lines: Vec<String>,
+/// A column of a table
+#[derive(Clone, Debug)]
+pub struct TableColumn {
+    pub alignment: TableAlignment,
+    /// The header cell followed by the body cells, without the spaces around them
+    pub lines: Vec<String>,
+}
+
+/// A table found by `parse_tables`
+#[derive(Clone, Debug)]
+pub struct ParsedTable {
+    /// The 1-based lines of the input where the table starts (its header) and ends (its last row)
+    pub start_line: usize,
+    pub end_line: usize,
+    pub columns: Vec<TableColumn>,
 }
 
 struct Table {
     columns: Vec<TableColumn>,
+    /// The line of the input where the header is
+    line: usize,
+    /// The widths set for some columns by a `<!-- mdfmt:col-width COLUMN=WIDTH -->` directive, instead of `max_col_width`
+    max_widths: Vec<(usize, usize)>,
+    /// How many block quotes the table is nested in, and the markers written before each of its rows
+    quote_depth: usize,
+    prefix: String,
 }
 
 enum ParseState {
     RegularText,
+    IndentedCode,
     CheckingHeader {
         source_header: String,
         headers: Vec<String>,
+        quote_depth: usize,
     },
     ReadingTable {
         source_table: Vec<String>,
@@ -37,100 +65,1013 @@ impl ParseState {
     }
 }
 
+impl TableColumn {
+    /// The alignment to write in the delimiter row, which may be inferred from the column's content
+    fn output_alignment(&self, options: &FormatOptions) -> TableAlignment {
+        if options.ascii_safe {
+            TableAlignment::None
+        } else if self.alignment == TableAlignment::None && options.auto_number_align && self.is_numeric() {
+            TableAlignment::Right
+        } else {
+            self.alignment
+        }
+    }
+
+    /// Whether all the non-empty body cells are numbers, and there is at least one
+    fn is_numeric(&self) -> bool {
+        let mut body_cells = self.lines.iter().skip(1).filter(|cell| !cell.is_empty()).peekable();
+        body_cells.peek().is_some() && body_cells.all(|cell| is_number(cell))
+    }
+
+    /// Whether the body cells mix numbers with other non-empty cells
+    fn is_mixed(&self) -> bool {
+        let body_cells = self.lines.iter().skip(1).filter(|cell| !cell.is_empty());
+        body_cells.clone().any(|cell| is_number(cell)) && !body_cells.clone().all(|cell| is_number(cell))
+    }
+}
+
 impl Table {
-    pub fn write_output(&self, output: &mut String) {
-        let column_widths = self.columns.iter().map(|column| {
-           column.lines.iter().map(|l| l.width()).max().unwrap().max(1)
+    /// The width each column's cells are wrapped to, from a `col-width` directive or `max_col_width`
+    fn column_max_widths(&self, options: &FormatOptions) -> Vec<Option<usize>> {
+        (0..self.columns.len()).map(|i| {
+            self.max_widths.iter().rev().find(|&&(column, _)| column == i).map(|&(_, width)| width).or(options.max_col_width)
+        }).collect()
+    }
+
+    pub fn write_output(&self, output: &mut String, options: &FormatOptions) {
+        let max_widths = self.column_max_widths(options);
+        let wrapped;
+        let table = if max_widths.iter().any(Option::is_some) {
+            wrapped = self.wrap_cells(&max_widths);
+            &wrapped
+        } else {
+            self
+        };
+
+        // The widest integer and fractional parts of the numbers in each column whose decimal points are lined up
+        let decimal_widths = table.columns.iter().map(|column| {
+            (options.align_decimal && column.is_numeric()).then(|| {
+                column.lines.iter().skip(1).filter(|cell| !cell.is_empty()).fold((0, 0), |(integer, fraction), cell| {
+                    let (cell_integer, cell_fraction) = cell.split_at(decimal_point(cell));
+                    (integer.max(display_width(cell_integer)), fraction.max(display_width(cell_fraction)))
+                })
+            })
+        }).collect::<Vec<_>>();
+        // The header is the first line of each column, so the widest of the header and the body cells sets the width,
+        // and the delimiter row is written as wide as the cells
+        let column_widths = table.columns.iter().zip(&decimal_widths).map(|(column, decimal_width)| {
+           let numbers_width = decimal_width.map_or(0, |(integer, fraction)| integer + fraction);
+           column.lines.iter().map(|l| cell_width(l)).max().unwrap_or(1).max(numbers_width).max(options.min_col_width)
         }).collect::<Vec<_>>();
 
-        let lines = self.columns[0].lines.len();
-        self.write_output_line(output, &column_widths, 0);
-        self.write_subhead_line(output, &column_widths);
+        let outer_pipes = options.outer_pipes || table.needs_outer_pipes();
+        let lines = table.line_count();
+        table.write_output_line(output, &column_widths, &decimal_widths, 0, outer_pipes, options);
+        table.write_subhead_line(output, &column_widths, outer_pipes, options);
         for i in 1..lines {
-            self.write_output_line(output, &column_widths, i);
+            table.write_output_line(output, &column_widths, &decimal_widths, i, outer_pipes, options);
         }
     }
 
-    fn write_output_line(&self, output: &mut String, widths: &[usize], index: usize) {
-        output.push('|');
-        for (column, &width) in self.columns.iter().zip(widths) {
-            let elem = &column.lines[index];
-            let padded = pad_cell_content(elem, width);
-            output.push_str(&padded);
+    /// Whether the rows would no longer be read as the same table without their outer pipes
+    fn needs_outer_pipes(&self) -> bool {
+        let last = self.columns.len() - 1;
+        last == 0 || (0..self.line_count()).any(|i| {
+            let first_cell = self.columns[0].lines.get(i).map_or("", |cell| cell.trim());
+            let last_cell = self.columns[last].lines.get(i).map_or("", |cell| cell.trim());
+            first_cell.is_empty() || last_cell.is_empty()
+                || !starts_plain_paragraph(first_cell)
+                || Fence::open(first_cell, 0, 0).is_some()
+                || footnote_definition_len(first_cell).is_some()
+                || is_table_caption(first_cell)
+        })
+    }
+
+    /// Writes the formatted table in place of its `source`, and keeps what was parsed if `tables` is collecting it
+    fn finish(mut self, source: &[String], output: &mut String, options: &FormatOptions, diagnostics: &mut Vec<Diagnostic>, tables: &mut Option<Vec<ParsedTable>>) {
+        // The continuation lines of wrapped cells read back as rows of their own, sorting them would scatter the cells
+        let wraps = self.column_max_widths(options).iter().any(Option::is_some);
+        let mut sorted_mixed_column = false;
+        if let Some(sort_by) = options.sort_by.filter(|sort_by| options.format_tables && !wraps && sort_by.column < self.columns.len()) {
+            let column = &self.columns[sort_by.column];
+            let comparator = sort_by.comparator.unwrap_or_else(|| {
+                sorted_mixed_column = column.is_mixed();
+                if column.is_numeric() { SortComparator::Numeric } else { SortComparator::Lexical }
+            });
+            self.sort_rows(sort_by.column, comparator, sort_by.descending);
+        }
+        let start = output.len();
+        if options.format_tables {
+            self.write_output(output, options);
+        } else {
+            for line in source {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        if options.strict {
+            self.check_duplicate_headers(&source[0], diagnostics);
+        }
+        if options.lint_tables {
+            let formatted = output[start..].lines().collect::<Vec<_>>();
+            let table_width = formatted.iter().map(|line| display_width(line)).max().unwrap_or(0);
+            if let Some(max_width) = options.warn_table_width.filter(|&max_width| table_width > max_width) {
+                diagnostics.push(Diagnostic {
+                    line: self.line,
+                    column: header_column(&source[0]),
+                    severity: Severity::Lint,
+                    message: format!("The table is {} columns wide, more than {}", table_width, max_width),
+                });
+            }
+            // A table with no body is valid, but it's often one whose rows were lost or never written
+            if self.line_count() == 1 {
+                diagnostics.push(Diagnostic {
+                    line: self.line,
+                    column: header_column(&source[0]),
+                    severity: Severity::Lint,
+                    message: "The table has a header but no body rows".to_owned(),
+                });
+            }
+            if sorted_mixed_column {
+                diagnostics.push(Diagnostic {
+                    line: self.line,
+                    column: header_column(&source[0]),
+                    severity: Severity::Lint,
+                    message: "The rows were sorted lexically, since the column mixes numbers and text".to_owned(),
+                });
+            }
+            if formatted != source {
+                let changed_columns = count_changed_columns(source, &formatted, self.columns.len(), options);
+                let message = match changed_columns {
+                    0 => "The table was reformatted".to_owned(),
+                    1 => "The table was reformatted (reflowed 1 column)".to_owned(),
+                    count => format!("The table was reformatted (reflowed {} columns)", count),
+                };
+                diagnostics.push(Diagnostic {
+                    line: self.line,
+                    column: header_column(&source[0]),
+                    severity: Severity::Lint,
+                    message,
+                });
+            }
+        }
+        if let Some(tables) = tables {
+            tables.push(ParsedTable {
+                start_line: self.line,
+                // The delimiter row is the only one that isn't in the columns
+                end_line: self.line + self.line_count(),
+                columns: self.columns,
+            });
+        }
+    }
+
+    /// Warns about the non-empty headers that appear in several columns, which is often a copy-paste mistake
+    fn check_duplicate_headers(&self, source_header: &str, diagnostics: &mut Vec<Diagnostic>) {
+        let headers = self.columns.iter().map(|column| column.lines[0].as_str()).collect::<Vec<_>>();
+        for (i, header) in headers.iter().enumerate() {
+            if header.is_empty() || headers[..i].contains(header) {
+                continue;
+            }
+            let columns = headers.iter().enumerate()
+                .filter(|(_, other)| other == &header)
+                .map(|(j, _)| (j + 1).to_string())
+                .collect::<Vec<_>>();
+            if let [first @ .., last] = columns.as_slice() {
+                if !first.is_empty() {
+                    diagnostics.push(Diagnostic {
+                        line: self.line,
+                        column: header_column(source_header),
+                        severity: Severity::Warning,
+                        message: format!("The header '{}' is repeated in columns {} and {}", header, first.join(", "), last),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Sorts the body rows by the cells of one column, the rows with equal cells keep their order
+    fn sort_rows(&mut self, column: usize, comparator: SortComparator, descending: bool) {
+        let key_cells = &self.columns[column].lines;
+        let mut order = (1..self.line_count()).collect::<Vec<_>>();
+        order.sort_by(|&a, &b| compare_cells(&key_cells[a], &key_cells[b], comparator, descending));
+        for column in &mut self.columns {
+            let mut lines = std::mem::take(&mut column.lines);
+            column.lines = std::iter::once(std::mem::take(&mut lines[0]))
+                .chain(order.iter().map(|&i| std::mem::take(&mut lines[i])))
+                .collect();
+        }
+    }
+
+    fn line_count(&self) -> usize {
+        self.columns.iter().map(|column| column.lines.len()).max().unwrap_or(0)
+    }
+
+    /// Wraps the body cells wider than `max_width` on continuation rows, where the other cells are left empty.
+    /// The header is never wrapped, since its continuation rows would become part of the body.
+    fn wrap_cells(&self, max_widths: &[Option<usize>]) -> Table {
+        let mut columns = self.columns.iter().map(|column| TableColumn {
+            alignment: column.alignment,
+            lines: column.lines.iter().take(1).cloned().collect(),
+        }).collect::<Vec<_>>();
+
+        for i in 1..self.line_count() {
+            let wrapped_row = self.columns.iter().zip(max_widths).map(|(column, max_width)| match (column.lines.get(i), max_width) {
+                (Some(cell), Some(max_width)) => wrap_cell(cell, *max_width),
+                (Some(cell), None) => vec![cell.clone()],
+                (None, _) => Vec::new(),
+            }).collect::<Vec<_>>();
+            let height = wrapped_row.iter().map(Vec::len).max().unwrap_or(0).max(1);
+            for (column, mut cell_lines) in columns.iter_mut().zip(wrapped_row) {
+                cell_lines.resize(height, String::new());
+                column.lines.extend(cell_lines);
+            }
+        }
+
+        Table {
+            columns,
+            line: self.line,
+            max_widths: self.max_widths.clone(),
+            quote_depth: self.quote_depth,
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    fn write_output_line(&self, output: &mut String, widths: &[usize], decimal_widths: &[Option<(usize, usize)>], index: usize, outer_pipes: bool, options: &FormatOptions) {
+        output.push_str(&self.prefix);
+        if outer_pipes {
             output.push('|');
         }
+        for (i, (column, &width)) in self.columns.iter().zip(widths).enumerate() {
+            let elem = column.lines.get(index).map_or("", String::as_str).trim();
+            if options.compact {
+                output.push_str(elem);
+            } else {
+                let alignment = if options.justify_cells { column.output_alignment(options) } else { TableAlignment::None };
+                let margins = cell_margins(i, self.columns.len(), options.pipe_style, outer_pipes);
+                let aligned_number;
+                let content = match decimal_widths[i] {
+                    Some((integer_width, fraction_width)) if index > 0 && !elem.is_empty() => {
+                        let (integer, fraction) = elem.split_at(decimal_point(elem));
+                        aligned_number = format!("{}{}{}", " ".repeat(integer_width - display_width(integer)), elem,
+                                                 " ".repeat(fraction_width - display_width(fraction)));
+                        &aligned_number
+                    },
+                    _ => elem,
+                };
+                output.push_str(&pad_cell_content(content, width, alignment, margins));
+            }
+            if outer_pipes || i + 1 < self.columns.len() {
+                output.push('|');
+            }
+        }
+        if !outer_pipes {
+            // The padding of the last cell would be trailing whitespace
+            output.truncate(output.trim_end_matches(' ').len());
+        }
         output.push('\n');
     }
 
-    fn write_subhead_line(&self, output: &mut String, widths: &[usize]) {
-        output.push('|');
-        for (column, &width) in self.columns.iter().zip(widths) {
-            match column.alignment {
-                TableAlignment::Left | TableAlignment::Center => output.push(':'),
-                _ => output.push('-'),
-            };
-            output.push_str(&"-".repeat(width));
-            match column.alignment {
-                TableAlignment::Right | TableAlignment::Center => output.push(':'),
-                _ => output.push('-'),
-            };
+    /// Writes the delimiter row, whose cells are as wide as the padded cells of the other rows with their margins:
+    /// the colons take the place of the spaces around the content, and there is always a dash so `:-:` stays valid
+    fn write_subhead_line(&self, output: &mut String, widths: &[usize], outer_pipes: bool, options: &FormatOptions) {
+        output.push_str(&self.prefix);
+        if outer_pipes {
             output.push('|');
         }
+        for (i, (column, &width)) in self.columns.iter().zip(widths).enumerate() {
+            let (left_margin, right_margin) = cell_margins(i, self.columns.len(), options.pipe_style, outer_pipes);
+            // Short delimiters are the shortest that are still valid, like `---` or `:-:`
+            let cell_width = if options.compact || !options.fit_delimiter_width { 3 } else { left_margin + width + right_margin };
+            let alignment = column.output_alignment(options);
+            let left_colon = matches!(alignment, TableAlignment::Left | TableAlignment::Center);
+            let right_colon = matches!(alignment, TableAlignment::Right | TableAlignment::Center);
+            if left_colon {
+                output.push(':');
+            }
+            output.push_str(&"-".repeat(cell_width.saturating_sub(usize::from(left_colon) + usize::from(right_colon)).max(1)));
+            if right_colon {
+                output.push(':');
+            }
+            if outer_pipes || i + 1 < self.columns.len() {
+                output.push('|');
+            }
+        }
         output.push('\n');
     }
 }
 
-pub fn format_content(content: &str, strict: bool) -> Result<String, Box<dyn Error>> {
-    let mut result = String::new();
+/// The result of formatting a document
+#[derive(Clone, Debug)]
+pub struct Formatted {
+    /// The whole formatted document
+    pub content: String,
+    /// The problems found in the input, only reported when `FormatOptions::strict` is set
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// Formats every table found in `content`.
+///
+/// Code blocks, HTML blocks and other regular text are left untouched, a code block that is never closed runs to the end of the document.
+/// When `options.strict` is set, tables that appear broken and unclosed code blocks are reported as diagnostics instead of being silently ignored.
+///
+/// An empty `content` gives an empty output whatever the options. Blank lines are handled the same way wherever they are,
+/// so with the default options `"\n"` and `"\n\n\n"` both give `"\n"`, and `"  \n\t\n "` gives `""` since it doesn't end with a newline.
+pub fn format_content(content: &str, options: &FormatOptions) -> Result<Formatted, FormatError> {
+    let mut formatted = String::with_capacity(content.len());
+    let diagnostics = format_in_parts(content, options, |part| {
+        formatted.push_str(part);
+        Ok(())
+    })?;
+    Ok(Formatted { content: formatted, diagnostics })
+}
+
+/// Formats a document like `format_content`, but writes the output to `writer` as soon as each part of it is complete
+/// instead of building it in a string, and returns the diagnostics.
+///
+/// Only what may still change is held back, like a table until its last row, so the output is never in memory at once.
+/// The writer isn't buffered here, a `BufWriter` avoids writing it line by line.
+pub fn format_to_writer(content: &str, options: &FormatOptions, writer: &mut impl Write) -> Result<Vec<Diagnostic>, FormatError> {
+    let diagnostics = format_in_parts(content, options, |part| writer.write_all(part.as_bytes()))?;
+    writer.flush()?;
+    Ok(diagnostics)
+}
+
+/// Formats `content`, giving each part of the output to `write` once it's complete, so that the whole output is the same
+/// whether it's written as it goes or collected at the end
+fn format_in_parts(content: &str, options: &FormatOptions, mut write: impl FnMut(&str) -> io::Result<()>) -> Result<Vec<Diagnostic>, FormatError> {
+    let mut formatter = document_formatter(content, options)?;
+    for line in content.split_inclusive('\n') {
+        formatter.push_line(line)?;
+        write(&formatter.take_output())?;
+    }
+    let Formatted { content, diagnostics } = formatter.finish();
+    write(&content)?;
+    Ok(diagnostics)
+}
+
+/// A formatter for the whole `content`, which already knows the line ending and footnote numbers it needs from all of it
+fn document_formatter<'a>(content: &str, options: &'a FormatOptions) -> Result<Formatter<'a>, FormatError> {
+    let use_crlf = match options.line_ending {
+        LineEnding::Auto => is_mostly_crlf(content),
+        LineEnding::Lf => false,
+        LineEnding::Crlf => true,
+    };
+    let mut formatter = Formatter::with_crlf(options, Some(use_crlf));
+    if options.renumber_footnotes {
+        formatter.footnotes = Some(number_footnotes(content, options)?);
+    }
+    Ok(formatter)
+}
 
-    let mut state = ParseState::new();
-    let mut is_in_code = true; // When inside ``` code blocks
-    for chunk in content.split("```") {
-        is_in_code = !is_in_code;
-        if is_in_code {
-            result.push_str(&format!("```{}```", chunk));
-            continue
+/// Formats a document like `format_content`, after checking that it is valid UTF-8
+pub fn format_bytes(content: &[u8], options: &FormatOptions) -> Result<Formatted, FormatError> {
+    let content = std::str::from_utf8(content).map_err(|err| FormatError::invalid_utf8(content, err.valid_up_to(), 1))?;
+    format_content(content, options)
+}
+
+/// Returns the tables found in `content`, as they would be formatted by `format_content` with the same options
+pub fn parse_tables(content: &str, options: &FormatOptions) -> Result<Vec<ParsedTable>, FormatError> {
+    let mut formatter = Formatter::new(options);
+    formatter.tables = Some(Vec::new());
+    if options.renumber_footnotes {
+        formatter.footnotes = Some(number_footnotes(content, options)?);
+    }
+    for line in content.split_inclusive('\n') {
+        formatter.push_line(line)?;
+    }
+    formatter.finish_output();
+    Ok(formatter.tables.unwrap_or_default())
+}
+
+/// Formats only the tables that are entirely between the 1-based lines `start_line` and `end_line` (included),
+/// for editors that format a selection. Everything else is left exactly as it is.
+///
+/// The tables are found like `format_content` would find them in the whole document, so a range can't change
+/// what is a table, but a range that starts or ends inside a fenced code block is refused since it's likely a mistake.
+/// Only the diagnostics about the formatted tables are reported.
+pub fn format_range(content: &str, start_line: usize, end_line: usize, options: &FormatOptions) -> Result<Formatted, FormatError> {
+    let lines = content.split_inclusive('\n').collect::<Vec<_>>();
+    // The tables are formatted on their own, and the end of a table isn't the end of the document.
+    // Their footnotes aren't renumbered, since the references outside of them wouldn't be.
+    let options = &options.clone().final_newline(FinalNewline::Preserve).renumber_footnotes(false);
+    let mut formatter = Formatter::new(options);
+    formatter.tables = Some(Vec::new());
+    for (i, line) in lines.iter().enumerate() {
+        let line_num = i + 1;
+        if let Some(fence) = formatter.fence.as_ref().filter(|_| line_num == start_line) {
+            return Err(FormatError::SplitCodeBlock { line: fence.line });
+        }
+        formatter.push_line(line)?;
+        if let Some(fence) = formatter.fence.as_ref().filter(|_| line_num == end_line) {
+            return Err(FormatError::SplitCodeBlock { line: fence.line });
         }
+    }
+    formatter.finish_output();
 
-        result.push_str(&format_chunk(chunk, &mut state, strict)?);
+    let use_crlf = match options.line_ending {
+        LineEnding::Auto => is_mostly_crlf(content),
+        LineEnding::Lf => false,
+        LineEnding::Crlf => true,
+    };
+    let mut formatted = Formatted { content: String::new(), diagnostics: Vec::new() };
+    let mut copied = 0;
+    let tables = formatter.tables.unwrap_or_default();
+    for table in tables.iter().filter(|table| table.start_line >= start_line && table.end_line <= end_line) {
+        // The widths set by a directive right before the table still apply to it
+        let mut first = table.start_line - 1;
+        if first > copied && directive(lines[first - 1]).is_some_and(|directive| directive.starts_with("col-width ")) {
+            first -= 1;
+        }
+        formatted.content.push_str(&lines[copied..first].concat());
+        let mut table_formatter = Formatter::with_crlf(options, Some(use_crlf));
+        for line in &lines[first..table.end_line] {
+            table_formatter.push_line(line)?;
+        }
+        let Formatted { content, diagnostics } = table_formatter.finish();
+        formatted.content.push_str(&content);
+        formatted.diagnostics.extend(diagnostics.into_iter().map(|diagnostic| Diagnostic { line: diagnostic.line + first, ..diagnostic }));
+        copied = table.end_line;
     }
+    formatted.content.push_str(&lines[copied..].concat());
+    Ok(formatted)
+}
 
-    if let ParseState::CheckingHeader{source_header, ..} = state {
-        result.push_str(&format!("{}\n", source_header));
-    } else if let ParseState::ReadingTable{table, ..} = state {
-        table.write_output(&mut result);
+/// Numbers the footnotes of `content` in the order of their first reference, which can come after their definition
+fn number_footnotes(content: &str, options: &FormatOptions) -> Result<FootnoteNumbers, FormatError> {
+    let mut formatter = Formatter::new(options);
+    for line in content.split_inclusive('\n') {
+        formatter.push_line(line)?;
     }
+    formatter.finish_output();
+    Ok(formatter.footnotes.unwrap_or_default().by_first_reference())
+}
+
+fn is_mostly_crlf(content: &str) -> bool {
+    let crlf_count = content.matches("\r\n").count();
+    let lf_count = content.matches('\n').count() - crlf_count;
+    crlf_count > lf_count
+}
 
-    Ok(result)
+/// Formats a document one line at a time, for input too large to hold in memory.
+///
+/// The output of each line is available as soon as nothing that follows can change it,
+/// only tables and paragraphs that may be setext headings are held back until they end.
+/// With `LineEnding::Auto`, the output uses the line ending of the first line, since the most common one isn't known yet.
+pub struct Formatter<'a> {
+    options: &'a FormatOptions,
+    use_crlf: Option<bool>, // Decided by the first line in auto mode
+    state: ParseState,
+    fence: Option<Fence>, // When inside a fenced code block
+    code_block: Vec<String>, // The lines of the fenced code block, held back until it's closed when its fences are normalized
+    ignoring: bool, // In the block after a `<!-- mdfmt:ignore -->` directive, which is copied verbatim
+    ignored_fence: Option<Fence>, // When the ignored block is a fenced code block, which can contain blank lines
+    html_block: Option<HtmlBlock>, // Inside a block of raw HTML, which is copied verbatim
+    column_widths: Vec<(usize, usize)>, // Set by a `<!-- mdfmt:col-width -->` directive, for the table on the next line
+    disabled: bool, // Between `<!-- mdfmt:off -->` and `<!-- mdfmt:on -->`, where every line is copied verbatim
+    line_num: usize,
+    list_items: Vec<usize>, // The columns where the content of the open list items starts, innermost last
+    // Reset after every code block, like the text they separate
+    in_paragraph: bool, // Indented code can't interrupt a paragraph
+    after_link_definition: bool, // Link reference definitions can't interrupt a paragraph, but can follow each other
+    in_footnote: bool, // In the first paragraph of a footnote definition, which is never wrapped nor made a heading
+    blank_lines: usize,
+    text_state: TextState,
+    paragraph_start: Option<usize>, // Where the current plain paragraph starts in the output, in case it's a setext heading
+    output: String,
+    input_terminated: bool, // Whether the last line had a line ending
+    diagnostics: Vec<Diagnostic>,
+    tables: Option<Vec<ParsedTable>>, // Only collected by parse_tables
+    footnotes: Option<FootnoteNumbers>, // When renumbering footnotes
 }
 
-/// Returns the formatted chunk (may delay output if a table spans multiple chunks)
-fn format_chunk(chunk: &str, state: &mut ParseState, strict: bool) -> Result<String, Box<dyn Error>> {
-    let mut output = String::new();
+impl<'a> Formatter<'a> {
+    pub fn new(options: &'a FormatOptions) -> Self {
+        let use_crlf = match options.line_ending {
+            LineEnding::Auto => None,
+            LineEnding::Lf => Some(false),
+            LineEnding::Crlf => Some(true),
+        };
+        Formatter::with_crlf(options, use_crlf)
+    }
+
+    fn with_crlf(options: &'a FormatOptions, use_crlf: Option<bool>) -> Self {
+        Formatter {
+            options,
+            use_crlf,
+            state: ParseState::new(),
+            fence: None,
+            code_block: Vec::new(),
+            ignoring: false,
+            ignored_fence: None,
+            html_block: None,
+            column_widths: Vec::new(),
+            disabled: false,
+            line_num: 0,
+            list_items: Vec::new(),
+            in_paragraph: false,
+            after_link_definition: false,
+            in_footnote: false,
+            blank_lines: 0,
+            text_state: TextState::default(),
+            paragraph_start: None,
+            output: String::new(),
+            input_terminated: true,
+            diagnostics: Vec::new(),
+            tables: None,
+            footnotes: options.renumber_footnotes.then(FootnoteNumbers::default),
+        }
+    }
+
+    /// Formats the next line of the document like `push_line`, after checking that it is valid UTF-8
+    pub fn push_line_bytes(&mut self, line: &[u8]) -> Result<(), FormatError> {
+        let line = std::str::from_utf8(line).map_err(|err| FormatError::invalid_utf8(line, err.valid_up_to(), self.line_num + 1))?;
+        self.push_line(line)
+    }
+
+    /// Formats the next line of the document, which includes its line ending unless it is the last one
+    pub fn push_line(&mut self, line: &str) -> Result<(), FormatError> {
+        if line.is_empty() {
+            return Ok(());
+        }
+        self.line_num += 1;
+        // A carriage return is a line ending too, the last line can end with one since lines are split after line feeds
+        self.input_terminated = line.ends_with('\n') || line.ends_with('\r');
+        // A byte order mark would stop the first line from being recognized as a table or a heading
+        let line = match line.strip_prefix('\u{FEFF}') {
+            Some(line) if self.line_num == 1 => {
+                if self.options.keep_bom {
+                    self.output.push('\u{FEFF}');
+                }
+                line
+            },
+            _ => line,
+        };
+        if self.use_crlf.is_none() {
+            self.use_crlf = Some(line.ends_with("\r\n"));
+        }
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if self.disabled {
+            if directive(line) != Some("on") {
+                self.output.push_str(line);
+                self.output.push('\n');
+                return Ok(());
+            }
+            // Formatting starts again with the directive, which is the first line of the next block
+            self.disabled = false;
+        }
+        if self.ignoring {
+            if let Some(fence) = &self.ignored_fence {
+                if fence.is_closed_by(line) {
+                    self.ignored_fence = None;
+                }
+            } else if line.trim().is_empty() {
+                // The blank line ending the block is formatted like any other
+                self.ignoring = false;
+            } else {
+                self.ignored_fence = Fence::open(line, self.line_num, self.list_items.last().copied().unwrap_or(0));
+            }
+            if self.ignoring {
+                self.output.push_str(line);
+                self.output.push('\n');
+                return Ok(());
+            }
+        }
+
+        if let Some(html_block) = &self.html_block {
+            let closed = html_block.is_closed_by(line);
+            if closed {
+                self.html_block = None;
+            }
+            // The blank line after a block that runs until one isn't part of it
+            if !(closed && line.trim().is_empty()) {
+                self.output.push_str(line);
+                self.output.push('\n');
+                return Ok(());
+            }
+        }
+
+        if self.fence.is_none() {
+            track_list_items(&mut self.list_items, line, self.in_paragraph);
+        }
+        if let Some(open_fence) = &self.fence {
+            let closed = open_fence.is_closed_by(line);
+            if let Some(len) = self.options.normalize_code_fence {
+                self.code_block.push(line.to_owned());
+                if closed {
+                    write_code_block(&mut self.output, &self.code_block, len, open_fence.indent);
+                    self.code_block.clear();
+                    self.fence = None;
+                }
+                return Ok(());
+            }
+            if closed {
+                self.fence = None;
+            }
+        } else if let Some(open_fence) = Fence::open(line, self.line_num, self.list_items.last().copied().unwrap_or(0)) {
+            end_paragraph(&mut self.output, &mut self.paragraph_start, self.options);
+            flush_state(&mut self.state, &mut self.output, self.options, &mut self.diagnostics, &mut self.tables);
+            self.in_paragraph = false;
+            self.in_footnote = false;
+            self.blank_lines = 0;
+            self.text_state = TextState::default();
+            self.fence = Some(open_fence);
+            if self.options.normalize_code_fence.is_some() {
+                self.code_block.push(line.to_owned());
+                return Ok(());
+            }
+        } else {
+            return self.format_text_line(line);
+        }
+        // Code blocks are copied verbatim
+        self.output.push_str(line);
+        self.output.push('\n');
+        Ok(())
+    }
 
-    for line in chunk.lines() {
-        *state = match state {
-            ParseState::RegularText => process_regular_text(line)?,
-            ParseState::CheckingHeader{source_header, headers} => process_header(line, &mut output, source_header, headers)?,
-            ParseState::ReadingTable{source_table, table} => process_table(line, &mut output, source_table, table, strict)?,
+    fn format_text_line(&mut self, line: &str) -> Result<(), FormatError> {
+        let options = self.options;
+        let output = &mut self.output;
+        let state = &mut self.state;
+        let indented_code = match state {
+            ParseState::RegularText => !self.in_paragraph && is_indented_code(line, options.tab_width),
+            ParseState::IndentedCode => line.trim().is_empty() || is_indented_code(line, options.tab_width),
+            _ => false,
+        };
+        let renumbered;
+        let line = match &mut self.footnotes {
+            Some(numbers) if !indented_code => {
+                renumbered = renumber_footnotes(line, numbers);
+                &*renumbered
+            },
+            _ => line,
+        };
+        // A table header that turned out to be a plain line is a paragraph, which a `---` line makes a heading
+        let after_header = matches!(state, ParseState::CheckingHeader{quote_depth: 0, ..});
+        let underlines_paragraph = self.paragraph_start.is_some() && setext_underline_level(line).is_some();
+        let can_define_link = !self.in_paragraph || std::mem::take(&mut self.after_link_definition);
+        *state = match std::mem::replace(state, ParseState::RegularText) {
+            ParseState::RegularText | ParseState::IndentedCode if indented_code => ParseState::IndentedCode,
+            ParseState::RegularText | ParseState::IndentedCode => process_regular_text(line, options)?,
+            ParseState::CheckingHeader{source_header, headers, quote_depth} => process_header(line, self.line_num, output, &source_header, &headers, quote_depth, options)?,
+            ParseState::ReadingTable{source_table, table} => process_table(line, output, source_table, table, options, &mut self.diagnostics, &mut self.tables)?,
         };
 
-        if let ParseState::RegularText = state {
-            output.push_str(line);
+        let mut footnote_paragraph = false;
+        // Whether the line was written as an ATX heading, which it can become when `fix_heading_space` is set
+        let mut wrote_heading = false;
+        match state {
+            ParseState::RegularText if HtmlBlock::open(line).is_some() => {
+                let html_block = HtmlBlock::open(line).unwrap();
+                self.blank_lines = 0;
+                end_paragraph(output, &mut self.paragraph_start, options);
+                output.push_str(line);
+                output.push('\n');
+                if !html_block.is_closed_by(line) {
+                    self.html_block = Some(html_block);
+                }
+                self.text_state.interrupt();
+            },
+            ParseState::RegularText => {
+                let setext_level = self.paragraph_start.and(setext_underline_level(line));
+                if let (Some(start), Some(level)) = (self.paragraph_start, setext_level) {
+                    if options.heading_style != HeadingStyle::Preserve {
+                        write_setext_heading(output, start, level, options.heading_style);
+                        self.paragraph_start = None;
+                        self.in_paragraph = false;
+                        return Ok(());
+                    }
+                }
+
+                self.blank_lines = if line.trim().is_empty() { self.blank_lines + 1 } else { 0 };
+                let footnote_definition = footnote_definition_len(line).is_some();
+                let continues_footnote = self.in_footnote && !footnote_definition && starts_plain_paragraph(line.trim_start());
+                footnote_paragraph = footnote_definition || continues_footnote;
+                if setext_level.is_some() {
+                    // The paragraph is a heading, which isn't wrapped
+                    self.paragraph_start = None;
+                } else if !starts_plain_paragraph(line) || footnote_paragraph || is_table_caption(strip_quote(line).1) {
+                    end_paragraph(output, &mut self.paragraph_start, options);
+                } else if !self.in_paragraph {
+                    self.paragraph_start = Some(output.len());
+                }
+                let rule = options.thematic_break.as_ref().filter(|_| {
+                    setext_level.is_none() && !(after_header && setext_underline_level(line) == Some(2)) && is_rule(line)
+                });
+                if !matches!(options.max_blank_lines, Some(max) if self.blank_lines > max) {
+                    let mut normalized = normalize_text_line(line, options, &mut self.text_state);
+                    if options.normalize_link_definitions && can_define_link {
+                        if let Some(definition) = normalize_link_definition(&normalized, options.lowercase_link_labels) {
+                            normalized = definition.into();
+                            self.after_link_definition = true;
+                        }
+                    }
+                    match rule {
+                        Some(rule) => {
+                            output.push_str(&line[..line.len() - line.trim_start().len()]);
+                            output.push_str(&rule.0);
+                        },
+                        // The lines continuing a footnote are indented like its next paragraphs must be
+                        None if continues_footnote => {
+                            output.push_str("    ");
+                            output.push_str(normalized.trim_start());
+                        },
+                        None => output.push_str(&normalized),
+                    }
+                    output.push('\n');
+                    wrote_heading = is_atx_heading(&normalized);
+                }
+            },
+            ParseState::IndentedCode => {
+                output.push_str(line);
+                output.push('\n');
+            },
+            _ => {
+                self.blank_lines = 0;
+                self.text_state.interrupt();
+                end_paragraph(output, &mut self.paragraph_start, options);
+            },
+        }
+        match state {
+            // The header of the table after the directive, its widths are kept until its delimiter row is read
+            ParseState::CheckingHeader{..} => {},
+            ParseState::ReadingTable{table, ..} if table.line + 1 == self.line_num => table.max_widths = std::mem::take(&mut self.column_widths),
+            _ => self.column_widths.clear(),
+        }
+        let in_text = matches!(state, ParseState::RegularText);
+        // Headings, horizontal rules and HTML blocks can't be continued by the next line
+        let ends_block = underlines_paragraph || wrote_heading || is_atx_heading(line) || is_rule(line) || HtmlBlock::open(line).is_some();
+        self.in_paragraph = in_text && !line.trim().is_empty() && !ends_block;
+        self.in_footnote = footnote_paragraph && self.in_paragraph;
+        if in_text {
+            self.start_directive(line);
+        }
+        Ok(())
+    }
+
+    /// Starts ignoring the next block or turns formatting off if `line` is one of these directives
+    fn start_directive(&mut self, line: &str) {
+        match directive(line) {
+            Some("ignore") => self.ignoring = true,
+            // Turned off until the end of the document if it's never turned on again
+            Some("off") => self.disabled = true,
+            Some(directive) if directive.starts_with("col-width ") => match parse_column_widths(&directive["col-width ".len()..]) {
+                Some(widths) => self.column_widths = widths,
+                None if self.options.strict => self.diagnostics.push(Diagnostic {
+                    line: self.line_num,
+                    column: 1 + line.len() - line.trim_start().len(),
+                    severity: Severity::Warning,
+                    message: "The column widths are invalid, they will be ignored (expected COLUMN=WIDTH pairs, like '0=10 2=20')".to_owned(),
+                }),
+                None => {},
+            },
+            _ => {},
+        }
+        if self.ignoring || self.disabled {
+            self.text_state.interrupt();
+        }
+    }
+
+    /// Returns the output that is complete so far, and won't be returned again
+    pub fn take_output(&mut self) -> String {
+        let mut end = self.paragraph_start.unwrap_or(self.output.len());
+        // The last line ending is dropped if the input ends without one, which isn't known yet,
+        // and the blank lines before it too if they are stripped
+        if end == self.output.len() && self.options.final_newline == FinalNewline::Strip {
+            end = self.output.trim_end_matches('\n').len();
+        } else if end == self.output.len() && self.output.ends_with('\n') {
+            end -= 1;
+        }
+        let rest = self.output.split_off(end);
+        let taken = std::mem::replace(&mut self.output, rest);
+        if let Some(start) = &mut self.paragraph_start {
+            *start -= end;
+        }
+        self.convert_line_endings(taken)
+    }
+
+    /// Returns the rest of the output once all the lines were pushed, with the diagnostics of the whole document
+    pub fn finish(mut self) -> Formatted {
+        self.finish_output()
+    }
+
+    fn finish_output(&mut self) -> Formatted {
+        end_paragraph(&mut self.output, &mut self.paragraph_start, self.options);
+        match &self.fence {
+            None => flush_state(&mut self.state, &mut self.output, self.options, &mut self.diagnostics, &mut self.tables),
+            Some(fence) if self.options.strict => self.diagnostics.push(Diagnostic {
+                line: fence.line,
+                column: fence.column,
+                severity: Severity::Warning,
+                message: "The code block is never closed, the rest of the document is treated as code".to_owned(),
+            }),
+            Some(_) => {},
+        }
+        // A block that is never closed has no closing fence to normalize, so it's left as it is
+        for line in &self.code_block {
+            self.output.push_str(line);
+            self.output.push('\n');
+        }
+        // A table ending the document is flushed first, so it loses its final line ending like any other last line
+        match self.options.final_newline {
+            FinalNewline::Preserve if !self.input_terminated && self.output.ends_with('\n') => {
+                self.output.pop();
+            },
+            FinalNewline::Strip => self.output.truncate(self.output.trim_end_matches('\n').len()),
+            _ => {},
+        }
+        let content = std::mem::take(&mut self.output);
+        Formatted {
+            content: self.convert_line_endings(content),
+            diagnostics: std::mem::take(&mut self.diagnostics),
+        }
+    }
+
+    fn convert_line_endings(&self, output: String) -> String {
+        if self.use_crlf == Some(true) {
+            output.replace('\n', "\r\n")
+        } else {
+            output
+        }
+    }
+}
+
+/// Ends the plain paragraph at the end of the output, if any, and wraps it when `options.print_width` is set
+fn end_paragraph(output: &mut String, paragraph_start: &mut Option<usize>, options: &FormatOptions) {
+    if let (Some(start), Some(width)) = (paragraph_start.take(), options.print_width) {
+        let wrapped = wrap_paragraph(&output[start..], width);
+        output.truncate(start);
+        output.push_str(&wrapped);
+    }
+}
+
+/// Writes out whatever the parser was holding back, once there is no more text to process
+fn flush_state(state: &mut ParseState, output: &mut String, options: &FormatOptions, diagnostics: &mut Vec<Diagnostic>, tables: &mut Option<Vec<ParsedTable>>) {
+    match std::mem::replace(state, ParseState::RegularText) {
+        ParseState::RegularText | ParseState::IndentedCode => {},
+        ParseState::CheckingHeader{source_header, ..} => output.push_str(&format!("{}\n", source_header)),
+        ParseState::ReadingTable{source_table, table} => table.finish(&source_table, output, options, diagnostics, tables),
+    }
+}
+
+/// Whether a line is a horizontal rule, and not indented enough to be part of the paragraph or code block before it
+fn is_rule(line: &str) -> bool {
+    let content = line.trim_start_matches(' ');
+    line.len() - content.len() <= 3 && is_thematic_break(content)
+}
+
+/// Returns the name of a directive written as an HTML comment on its own line, like `<!-- mdfmt:ignore -->`
+fn directive(line: &str) -> Option<&str> {
+    let comment = line.trim().strip_prefix("<!--")?.strip_suffix("-->")?;
+    comment.trim().strip_prefix("mdfmt:")
+}
+
+/// Parses the `COLUMN=WIDTH` pairs of a `col-width` directive, columns are counted from 0 and widths are at least 1
+fn parse_column_widths(pairs: &str) -> Option<Vec<(usize, usize)>> {
+    let widths = pairs.split_whitespace().map(|pair| {
+        let (column, width) = pair.split_once('=')?;
+        Some((column.parse().ok()?, width.parse::<usize>().ok()?.max(1)))
+    }).collect::<Option<Vec<_>>>()?;
+    if widths.is_empty() {
+        return None;
+    }
+    Some(widths)
+}
+
+/// Rewrites the paragraph at the end of the output, underlined by a setext heading marker, in the given style
+fn write_setext_heading(output: &mut String, paragraph_start: usize, level: usize, style: HeadingStyle) {
+    let text = output[paragraph_start..].lines().map(str::trim).collect::<Vec<_>>().join(" ");
+    match style {
+        HeadingStyle::Atx => {
+            output.truncate(paragraph_start);
+            output.push_str(&format!("{} {}\n", "#".repeat(level), text));
+        },
+        _ => {
+            // Multi-line setext headings keep their lines, only the underline is made to fit
+            let last_line = output[paragraph_start..].lines().last().unwrap_or("").trim().to_owned();
+            output.push_str(&setext_underline(level, &last_line));
             output.push('\n');
+        },
+    }
+}
+
+/// The opening line of a fenced code block
+struct Fence {
+    marker: char,
+    len: usize,
+    line: usize,
+    column: usize,
+    indent: usize, // Where the content of the list item the block is in starts
+}
+
+impl Fence {
+    /// Returns the fence opened by this line, if it starts a ``` or ~~~ fenced code block in a list item whose content starts at `indent`
+    fn open(line: &str, line_num: usize, indent: usize) -> Option<Fence> {
+        let fence = strip_fence_indent(line, indent)?;
+        let marker = fence.chars().next().filter(|&c| c == '`' || c == '~')?;
+        let len = fence.chars().take_while(|&c| c == marker).count();
+        if len < 3 {
+            return None;
+        }
+        // A backtick fence's info string can't contain backticks, otherwise this is an inline code span
+        if marker == '`' && fence[len..].contains('`') {
+            return None;
         }
+        Some(Fence { marker, len, line: line_num, column: 1 + line.len() - fence.len(), indent })
     }
 
-    Ok(output)
+    fn is_closed_by(&self, line: &str) -> bool {
+        let fence = match strip_fence_indent(line, self.indent) {
+            Some(fence) => fence,
+            None => return false,
+        };
+        let len = fence.chars().take_while(|&c| c == self.marker).count();
+        // A longer fence can embed shorter ones, e.g. ```` around a ``` example
+        len >= self.len && fence[len..].trim().is_empty()
+    }
 }
 
-fn process_regular_text(line: &str) -> Result<ParseState, Box<dyn Error>> {
-    let clean = line.trim();
-    if !clean.starts_with('|') || !clean.ends_with('|') {
-        return Ok(ParseState::RegularText);
+/// Writes a closed fenced code block with fences of at least `len`, its first and last lines being the fences
+fn write_code_block(output: &mut String, lines: &[String], len: usize, item_indent: usize) {
+    let opening = &lines[0];
+    let fence = opening.trim_start_matches(' ');
+    let indent = &opening[..opening.len() - fence.len()];
+    let marker = fence.chars().next().unwrap_or('`');
+    let info = fence.trim_start_matches(marker).trim();
+    let content = &lines[1..lines.len() - 1];
+    // Any run of the marker in the content must stay shorter than the fences, or it could close the block
+    let longest_run = content.iter()
+        .filter_map(|line| strip_fence_indent(line, item_indent))
+        .map(|line| line.chars().take_while(|&c| c == marker).count())
+        .max()
+        .unwrap_or(0);
+    let fence = marker.to_string().repeat(len.max(longest_run + 1));
+
+    output.push_str(&format!("{}{}{}\n", indent, fence, info));
+    for line in content {
+        output.push_str(line);
+        output.push('\n');
     }
+    output.push_str(&format!("{}{}\n", indent, fence));
+}
 
-    let headers = clean[1..].split_terminator('|').map(|header| header.trim().to_string()).collect::<Vec<_>>();
+/// Fences can be indented by up to three spaces, counted from `indent` in list items
+fn strip_fence_indent(line: &str, indent: usize) -> Option<&str> {
+    let fence = line.trim_start_matches(' ');
+    if line.len() - fence.len() > indent + 3 {
+        return None;
+    }
+    Some(fence)
+}
+
+/// Updates the columns where the content of the open list items starts with the next line outside of fenced code blocks.
+/// A line indented less than an item's content ends that item, unless it lazily continues its paragraph.
+fn track_list_items(list_items: &mut Vec<usize>, line: &str, in_paragraph: bool) {
+    let content = line.trim_start_matches(' ');
+    let indent = line.len() - content.len();
+    let item_indent = list_items.last().copied().unwrap_or(0);
+    // Blank lines don't end list items, and lines indented further are code in the item
+    if content.is_empty() || indent >= item_indent + 4 {
+        return;
+    }
+    let content_offset = list_item_content_offset(content);
+    if content_offset.is_none() && in_paragraph {
+        return;
+    }
+    list_items.retain(|&column| column <= indent);
+    if let Some(offset) = content_offset {
+        list_items.push(indent + offset);
+    }
+}
+
+/// Lines indented by four columns are code, unless they continue a paragraph
+fn is_indented_code(line: &str, tab_width: usize) -> bool {
+    let mut indent = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => indent += 1,
+            '\t' => indent += tab_width - indent % tab_width,
+            _ => return indent >= 4,
+        }
+        if indent >= 4 {
+            return !line.trim().is_empty();
+        }
+    }
+    false
+}
+
+/// Starts checking whether `line` is the header of a table, which is only known once the next line is read.
+///
+/// A delimiter row with nothing above it, like at the start of a document, is a header candidate like any row:
+/// `process_header` writes it back unchanged unless another delimiter row follows it, which makes a valid table
+/// with `---` headers. One ending the document is written back by `flush_state`.
+fn process_regular_text(line: &str, options: &FormatOptions) -> Result<ParseState, FormatError> {
+    let (quote_depth, content) = strip_quote(line);
+    let headers = match split_row(content, loose_pipes(options)) {
+        Some(headers) if !is_table_caption(content) => headers,
+        _ => return Ok(ParseState::RegularText),
+    };
     if headers.is_empty() {
         return Ok(ParseState::RegularText);
     }
@@ -138,17 +1079,19 @@ fn process_regular_text(line: &str) -> Result<ParseState, Box<dyn Error>> {
     Ok(ParseState::CheckingHeader {
         source_header: line.to_string(),
         headers,
+        quote_depth,
     })
 }
 
-fn process_header(line: &str, output: &mut String, source_header: &str, headers: &[String]) -> Result<ParseState, Box<dyn Error>> {
-    let clean = line.trim();
-    if !clean.starts_with('|') || !clean.ends_with('|') {
-        output.push_str(&format!("{}\n", source_header));
-        return Ok(ParseState::RegularText);
-    }
-
-    let sub_headers = clean[1..].split_terminator('|').map(|header| header.trim().to_string()).collect::<Vec<_>>();
+fn process_header(line: &str, line_num: usize, output: &mut String, source_header: &str, headers: &[String], quote_depth: usize, options: &FormatOptions) -> Result<ParseState, FormatError> {
+    let (line_depth, content) = strip_quote(line);
+    let sub_headers = match split_row(content, loose_pipes(options)) {
+        Some(sub_headers) if line_depth == quote_depth => sub_headers,
+        _ => {
+            output.push_str(&format!("{}\n", source_header));
+            return Ok(ParseState::RegularText);
+        }
+    };
     if sub_headers.len() != headers.len() {
         output.push_str(&format!("{}\n", source_header));
         return Ok(ParseState::RegularText);
@@ -156,37 +1099,16 @@ fn process_header(line: &str, output: &mut String, source_header: &str, headers:
 
     let mut columns = Vec::new();
     for (header, raw_sub) in headers.iter().zip(sub_headers) {
-        let sub = raw_sub.trim();
-        if sub.len() < 3 {
-            output.push_str(&format!("{}\n", source_header));
-            return Ok(ParseState::RegularText);
-        }
-        let align_left = sub.starts_with(':');
-        let align_right = sub.ends_with(':');
-
-        let mut dashes = sub;
-        if align_left {
-            dashes = &dashes[1..];
-        }
-        if align_left {
-            dashes = &dashes[..dashes.len()-1];
-        }
-        for c in dashes.chars() {
-            if c != '-' {
+        let alignment = match parse_delimiter_cell(&raw_sub, options.unicode_dashes) {
+            Some(alignment) => alignment,
+            None => {
                 output.push_str(&format!("{}\n", source_header));
                 return Ok(ParseState::RegularText);
             }
-        }
-
-        let alignment = match (align_left, align_right) {
-            (false, false) => TableAlignment::None,
-            (true, false) => TableAlignment::Left,
-            (false, true) => TableAlignment::Right,
-            (true, true) => TableAlignment::Center,
         };
         columns.push(TableColumn {
             alignment,
-            lines: vec![header.to_owned()],
+            lines: vec![cell_content(header, options)],
         })
     }
 
@@ -194,23 +1116,60 @@ fn process_header(line: &str, output: &mut String, source_header: &str, headers:
         source_table: vec![source_header.to_string(), line.to_string()],
         table: Table {
             columns,
+            line: line_num - 1,
+            max_widths: Vec::new(),
+            quote_depth,
+            prefix: table_prefix(source_header, options),
         }
     })
 }
 
-fn process_table(line: &str, output: &mut String, source_table: &[String], table: &Table, strict: bool) -> Result<ParseState, Box<dyn Error>> {
-    let clean = line.trim();
-    if !clean.starts_with('|') || !clean.ends_with('|') {
-        table.write_output(output);
-        return Ok(ParseState::RegularText);
+/// Returns the alignment set by a cell of the delimiter row, or None if it isn't one.
+/// A single dash is enough, like in `|-|` or `|:-:|`, anything but dashes and the outer colons makes it regular text.
+/// With `unicode_dashes`, the dashes can also be any of the unicode ones like `—`.
+fn parse_delimiter_cell(cell: &str, unicode_dashes: bool) -> Option<TableAlignment> {
+    let mut chars = cell.trim().chars().peekable();
+    let align_left = chars.next_if_eq(&':').is_some();
+    let mut dashes = 0;
+    while chars.next_if(|&c| c == '-' || (unicode_dashes && is_unicode_dash(c))).is_some() {
+        dashes += 1;
+    }
+    let align_right = chars.next_if_eq(&':').is_some();
+    if dashes == 0 || chars.next().is_some() {
+        return None;
     }
 
-    let columns = clean[1..].split_terminator('|').map(|header| header.trim().to_string()).collect::<Vec<_>>();
-    if columns.len() != table.columns.len() {
+    Some(match (align_left, align_right) {
+        (false, false) => TableAlignment::None,
+        (true, false) => TableAlignment::Left,
+        (false, true) => TableAlignment::Right,
+        (true, true) => TableAlignment::Center,
+    })
+}
+
+/// The hyphens, dashes and minus signs that word processors write instead of `-`
+fn is_unicode_dash(c: char) -> bool {
+    matches!(c, '\u{2010}'..='\u{2015}' | '\u{2212}' | '\u{FE58}' | '\u{FE63}' | '\u{FF0D}')
+}
+
+fn process_table(line: &str, output: &mut String, mut source_table: Vec<String>, mut table: Table, options: &FormatOptions, diagnostics: &mut Vec<Diagnostic>, tables: &mut Option<Vec<ParsedTable>>) -> Result<ParseState, FormatError> {
+    let (line_depth, content) = strip_quote(line);
+    let columns = match split_row(content, loose_pipes(options)) {
+        Some(columns) if line_depth == table.quote_depth && !is_table_caption(content) => columns,
+        _ => {
+            table.finish(&source_table, output, options, diagnostics, tables);
+            return Ok(ParseState::RegularText);
+        }
+    };
+    if columns.len() != table.columns.len() && options.strict_cell_count {
         // We consider that this is a broken table, not the end of a valid table, so we output the original text
-        if strict {
-            let line_num = 1 + output.as_bytes().iter().filter(|&&c| c==b'\n').count();
-            eprintln!("The table at line {} appears broken, it will not be formatted\n", line_num);
+        if options.strict {
+            diagnostics.push(Diagnostic {
+                line: table.line,
+                column: header_column(&source_table[0]),
+                severity: Severity::Warning,
+                message: format!("The table appears broken, it will not be formatted (expected {} cells in a row, found {})", table.columns.len(), columns.len()),
+            });
         }
         for line in source_table {
             output.push_str(&format!("{}\n", line));
@@ -218,24 +1177,361 @@ fn process_table(line: &str, output: &mut String, source_table: &[String], table
         return Ok(ParseState::RegularText);
     }
 
-    let mut source_table = source_table.to_vec();
-    source_table.push(line.to_owned());
+    if columns.len() > table.columns.len() && options.strict {
+        diagnostics.push(Diagnostic {
+            line: table.line + source_table.len(),
+            column: header_column(line),
+            severity: Severity::Warning,
+            message: format!("The row has {} cells, the ones after the {} of the header are dropped", columns.len(), table.columns.len()),
+        });
+    }
 
-    let mut table_columns = table.columns.clone();
-    for (table_column, column) in table_columns.iter_mut().zip(columns) {
-        table_column.lines.push(column);
+    source_table.push(line.to_owned());
+    // Like in GFM, short rows end with empty cells and the cells of long rows after the last column are ignored
+    let columns = columns.into_iter().chain(std::iter::repeat(String::new()));
+    for (table_column, column) in table.columns.iter_mut().zip(columns) {
+        table_column.lines.push(cell_content(&column, options));
     }
 
     Ok(ParseState::ReadingTable {
         source_table,
-        table: Table {
-            columns: table_columns,
-        }
+        table,
     })
 }
 
-fn pad_cell_content(elem: &str, width: usize) -> String {
-    let mut padded = format!(" {}", elem.trim());
-    padded.push_str(&" ".repeat(width + 2 - padded.width()));
-    padded
+/// The 1-based column where the content of a table's header starts, after its indentation and block quote markers
+fn header_column(header: &str) -> usize {
+    let (_, content) = strip_quote(header);
+    1 + header.len() - content.trim_start().len()
+}
+
+/// Counts the columns where a cell of the formatted rows isn't exactly the same as in the source, spaces included.
+/// Rows wrapped on continuation rows change every column after them.
+fn count_changed_columns(source: &[String], formatted: &[&str], column_count: usize, options: &FormatOptions) -> usize {
+    let mut changed = vec![source.len() != formatted.len(); column_count];
+    for (source_row, formatted_row) in source.iter().zip(formatted) {
+        let source_cells = split_cells(strip_quote(source_row).1, loose_pipes(options)).unwrap_or_default();
+        let formatted_cells = split_cells(strip_quote(formatted_row).1, loose_pipes(options)).unwrap_or_default();
+        for (i, changed) in changed.iter_mut().enumerate() {
+            *changed |= source_cells.get(i) != formatted_cells.get(i);
+        }
+    }
+    changed.into_iter().filter(|&changed| changed).count()
+}
+
+/// Splits off the block quote markers of a line, returns how many quotes it's nested in and the rest of the line
+fn strip_quote(line: &str) -> (usize, &str) {
+    parse_blockquote(line).map_or((0, line), |(_, depth, content)| (depth, content))
+}
+
+/// The markers to write before the rows of a table nested in block quotes, given its header line,
+/// or its indentation otherwise so that a table in a list item stays in it
+fn table_prefix(source_header: &str, options: &FormatOptions) -> String {
+    match parse_blockquote(source_header) {
+        None => source_header[..source_header.len() - source_header.trim_start().len()].to_owned(),
+        Some((_, _, content)) if options.blockquote_style == BlockquoteStyle::Preserve => {
+            source_header[..source_header.len() - content.len()].to_owned()
+        },
+        Some((indent, depth, _)) => format!("{} ", quote_markers(indent, depth, options.blockquote_style)),
+    }
+}
+
+/// Whether table rows may leave out their outer pipes, which the tables written without them need to be read back
+fn loose_pipes(options: &FormatOptions) -> bool {
+    options.allow_loose_pipes || !options.outer_pipes
+}
+
+/// Splits a `| a | b |` row into its trimmed cells, or returns None if the line isn't a table row.
+/// Pipes escaped with a backslash or inside an inline code span are kept verbatim as part of the cell content.
+/// With `allow_loose_pipes`, the outer pipes are optional as long as the row contains one (e.g. `a | b`).
+fn split_row(line: &str, allow_loose_pipes: bool) -> Option<Vec<String>> {
+    split_cells(line, allow_loose_pipes).map(|cells| cells.iter().map(|cell| cell.trim().to_owned()).collect())
+}
+
+/// Splits a row in its cells like `split_row`, but keeps the spaces around them
+fn split_cells(line: &str, allow_loose_pipes: bool) -> Option<Vec<String>> {
+    let clean = line.trim();
+    let leading_pipe = clean.starts_with('|');
+    if !leading_pipe && !allow_loose_pipes {
+        return None;
+    }
+
+    let row = if leading_pipe { &clean[1..] } else { clean };
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut terminated = false;
+    let mut i = 0;
+    while let Some(c) = row[i..].chars().next() {
+        terminated = false;
+        if c == '\\' {
+            let escaped_len = row[i+1..].chars().next().map_or(0, char::len_utf8);
+            cell.push_str(&row[i..i+1+escaped_len]);
+            i += 1 + escaped_len;
+        } else if c == '`' {
+            let fence_len = row[i..].bytes().take_while(|&b| b == b'`').count();
+            // An unbalanced backtick run is just literal text, and the pipes after it still split cells
+            let span_end = find_code_span_end(&row[i+fence_len..], fence_len).map_or(fence_len, |end| fence_len + end);
+            cell.push_str(&row[i..i+span_end]);
+            i += span_end;
+        } else if c == '|' {
+            cells.push(std::mem::take(&mut cell));
+            terminated = true;
+            i += 1;
+        } else {
+            cell.push(c);
+            i += c.len_utf8();
+        }
+    }
+
+    if !terminated {
+        if !allow_loose_pipes || (!leading_pipe && cells.is_empty()) {
+            return None;
+        }
+        cells.push(cell);
+    }
+    Some(cells)
+}
+
+/// Returns the offset just past the backtick run of exactly `fence_len` that closes a code span
+pub(crate) fn find_code_span_end(text: &str, fence_len: usize) -> Option<usize> {
+    let mut i = 0;
+    while let Some(start) = text[i..].find('`') {
+        let start = i + start;
+        let run_len = text[start..].bytes().take_while(|&b| b == b'`').count();
+        if run_len == fence_len {
+            return Some(start + run_len);
+        }
+        i = start + run_len;
+    }
+    None
+}
+
+/// Splits a cell's content in lines no wider than `max_width`, breaking between words when possible.
+/// Words are only broken if they don't contain code spans, so that a pipe can never be split from its escape or span.
+fn wrap_cell(cell: &str, max_width: usize) -> Vec<String> {
+    let segments = split_line_breaks(cell);
+    if segments.len() > 1 {
+        return segments.into_iter().flat_map(|segment| wrap_cell(segment.trim(), max_width)).collect();
+    }
+    if display_width(cell) <= max_width {
+        return vec![cell.to_owned()];
+    }
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in split_words(cell) {
+        for piece in break_word(word, max_width) {
+            if line.is_empty() {
+                line = piece;
+            } else if display_width(&line) + 1 + display_width(&piece) <= max_width {
+                line.push(' ');
+                line.push_str(&piece);
+            } else {
+                lines.push(std::mem::replace(&mut line, piece));
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Splits a cell after each of its `<br>` tags (or `<br/>`, in any case), which are kept at the end of their line.
+/// Tags in code spans or escaped are just text.
+fn split_line_breaks(cell: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut segment_start = 0;
+    let mut i = 0;
+    while let Some(c) = cell[i..].chars().next() {
+        match c {
+            '\\' => i += 1 + cell[i+1..].chars().next().map_or(0, char::len_utf8),
+            '`' => {
+                let fence_len = cell[i..].bytes().take_while(|&b| b == b'`').count();
+                i += find_code_span_end(&cell[i+fence_len..], fence_len).map_or(fence_len, |end| fence_len + end);
+            },
+            '<' => match line_break_len(&cell[i..]) {
+                Some(len) => {
+                    i += len;
+                    if !cell[i..].trim().is_empty() {
+                        segments.push(&cell[segment_start..i]);
+                        segment_start = i;
+                    }
+                },
+                None => i += 1,
+            },
+            _ => i += c.len_utf8(),
+        }
+    }
+    segments.push(&cell[segment_start..]);
+    segments
+}
+
+/// How wide a cell is once rendered: as wide as the longest of its lines if it has `<br>` tags, which don't count.
+/// Its source is then wider than its column, but the other cells aren't padded to the width of the tags.
+fn cell_width(cell: &str) -> usize {
+    split_line_breaks(cell).into_iter().map(|segment| {
+        let segment = segment.trim_end();
+        let text = match segment.rfind('<') {
+            Some(tag_start) if line_break_len(&segment[tag_start..]) == Some(segment.len() - tag_start) => &segment[..tag_start],
+            _ => segment,
+        };
+        display_width(text.trim())
+    }).max().unwrap_or(0)
+}
+
+/// The length of the `<br>` tag that `text` starts with, if it does
+fn line_break_len(text: &str) -> Option<usize> {
+    if !text.get(..3)?.eq_ignore_ascii_case("<br") {
+        return None;
+    }
+    let rest = text[3..].trim_start_matches([' ', '\t']);
+    let rest = rest.strip_prefix('/').unwrap_or(rest).strip_prefix('>')?;
+    Some(text.len() - rest.len())
+}
+
+/// Splits on the spaces that aren't inside code spans
+pub(crate) fn split_words(cell: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut word_start = 0;
+    let mut i = 0;
+    while let Some(c) = cell[i..].chars().next() {
+        if c == '\\' {
+            i += 1 + cell[i+1..].chars().next().map_or(0, char::len_utf8);
+        } else if c == '`' {
+            let fence_len = cell[i..].bytes().take_while(|&b| b == b'`').count();
+            i += find_code_span_end(&cell[i+fence_len..], fence_len).map_or(fence_len, |end| fence_len + end);
+        } else if c == ' ' {
+            if i > word_start {
+                words.push(&cell[word_start..i]);
+            }
+            i += 1;
+            word_start = i;
+        } else {
+            i += c.len_utf8();
+        }
+    }
+    if cell.len() > word_start {
+        words.push(&cell[word_start..]);
+    }
+    words
+}
+
+fn break_word(word: &str, max_width: usize) -> Vec<String> {
+    if display_width(word) <= max_width || word.contains('`') {
+        return vec![word.to_owned()];
+    }
+
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    for cluster in clusters(word) {
+        let escapes_next = piece.ends_with('\\') && !piece.ends_with("\\\\");
+        if !piece.is_empty() && !escapes_next && display_width(&piece) + display_width(cluster) > max_width {
+            pieces.push(std::mem::take(&mut piece));
+        }
+        piece.push_str(cluster);
+    }
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+    pieces
+}
+
+/// Compares the cells of the column rows are sorted by, the cells that aren't numbers come last in either order
+fn compare_cells(a: &str, b: &str, comparator: SortComparator, descending: bool) -> Ordering {
+    let ordering = match comparator {
+        SortComparator::Lexical => a.to_lowercase().cmp(&b.to_lowercase()),
+        SortComparator::Numeric => match (number_value(a), number_value(b)) {
+            (Some(a), Some(b)) => a.total_cmp(&b),
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => a.to_lowercase().cmp(&b.to_lowercase()),
+        },
+    };
+    if descending { ordering.reverse() } else { ordering }
+}
+
+/// The value of a cell that `is_number`, without its thousands separators, currency symbol or percent sign
+fn number_value(cell: &str) -> Option<f64> {
+    if !is_number(cell) {
+        return None;
+    }
+    let value = cell.chars().filter(|&c| c.is_ascii_digit() || c == '.').collect::<String>().parse::<f64>().ok()?;
+    Some(if cell.starts_with('-') { -value } else { value })
+}
+
+/// Where the decimal point of a number is, or where it would be for a number without one (like before the `%` of `12%`)
+fn decimal_point(number: &str) -> usize {
+    number.find('.').or_else(|| number.rfind(|c: char| c.is_ascii_digit()).map(|last_digit| last_digit + 1)).unwrap_or(number.len())
+}
+
+/// Whether a cell is a number like `-1,234.5`, optionally with a currency symbol or a percent sign
+fn is_number(cell: &str) -> bool {
+    let number = cell.trim_start_matches(['+', '-']);
+    let number = number.trim_start_matches(['$', '€', '£', '¥']).trim_end_matches(['$', '€', '£', '¥', '%']).trim();
+    let (integer, fraction) = match number.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (number, None),
+    };
+
+    let valid_integer = if integer.contains(',') {
+        // Thousands separators must delimit groups of three digits
+        let mut groups = integer.split(',');
+        let first = groups.next().unwrap_or("");
+        (1..=3).contains(&first.len()) && first.bytes().all(|b| b.is_ascii_digit())
+            && groups.all(|group| group.len() == 3 && group.bytes().all(|b| b.is_ascii_digit()))
+    } else {
+        integer.bytes().all(|b| b.is_ascii_digit())
+    };
+    let valid_fraction = fraction.is_none_or(|fraction| fraction.bytes().all(|b| b.is_ascii_digit()));
+    let has_digits = !integer.is_empty() || fraction.is_some_and(|fraction| !fraction.is_empty());
+    valid_integer && valid_fraction && has_digits
+}
+
+/// The content of a cell as it is formatted, with its tabs expanded and its runs of spaces collapsed if needed
+fn cell_content(cell: &str, options: &FormatOptions) -> String {
+    let expanded = expand_tabs(cell, options.tab_width);
+    if options.collapse_cell_spaces {
+        split_words(&expanded).join(" ")
+    } else {
+        expanded
+    }
+}
+
+/// Replaces the tabs of a cell with spaces up to the next tab stop, which starts counting at the cell's content
+fn expand_tabs(cell: &str, tab_width: usize) -> String {
+    if !cell.contains('\t') {
+        return cell.to_owned();
+    }
+
+    let mut expanded = String::new();
+    for c in cell.chars() {
+        if c == '\t' {
+            let spaces = tab_width - display_width(&expanded) % tab_width;
+            expanded.push_str(&" ".repeat(spaces));
+        } else {
+            expanded.push(c);
+        }
+    }
+    expanded
+}
+
+/// The number of spaces written between a column's content and the pipes before and after it,
+/// there are none at the start and end of the row without `outer_pipes`
+fn cell_margins(index: usize, column_count: usize, pipe_style: PipeStyle, outer_pipes: bool) -> (usize, usize) {
+    let outer = usize::from(outer_pipes && pipe_style != PipeStyle::TightOuter);
+    let inner = usize::from(pipe_style != PipeStyle::TightInner);
+    (if index == 0 { outer } else { inner }, if index + 1 == column_count { outer } else { inner })
+}
+
+/// Pads a cell with spaces up to `width` columns, plus the `margins` before and after the content
+fn pad_cell_content(content: &str, width: usize, alignment: TableAlignment, margins: (usize, usize)) -> String {
+    // Only the cells with `<br>` tags can be wider than their column
+    let padding = width.saturating_sub(display_width(content));
+    let left = match alignment {
+        TableAlignment::None | TableAlignment::Left => 0,
+        TableAlignment::Center => padding / 2,
+        TableAlignment::Right => padding,
+    };
+    format!("{}{}{}", " ".repeat(margins.0 + left), content, " ".repeat(padding - left + margins.1))
 }
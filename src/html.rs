@@ -0,0 +1,67 @@
+/// The tags that start an HTML block running to the next blank line, from the CommonMark specification
+const BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "base", "basefont", "blockquote", "body", "caption", "center", "col", "colgroup", "dd",
+    "details", "dialog", "dir", "div", "dl", "dt", "fieldset", "figcaption", "figure", "footer", "form", "frame", "frameset",
+    "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hr", "html", "iframe", "legend", "li", "link", "main", "menu",
+    "menuitem", "nav", "noframes", "ol", "optgroup", "option", "p", "param", "search", "section", "summary", "table",
+    "tbody", "td", "tfoot", "th", "thead", "title", "tr", "track", "ul",
+];
+
+/// The tags whose content can contain blank lines, so their block only ends with the closing tag
+const RAW_TAGS: &[&str] = &["pre", "script", "style", "textarea"];
+
+/// A block of raw HTML, which is copied verbatim since it can contain pipes and anything else that looks like Markdown
+pub(crate) enum HtmlBlock {
+    /// Ends with the line that contains this, in any case
+    Until(String),
+    /// Ends before the next blank line
+    UntilBlankLine,
+}
+
+impl HtmlBlock {
+    /// Returns the HTML block that `line` starts, if any.
+    ///
+    /// This only recognizes the blocks that can interrupt a paragraph, a line with any other tag is regular text.
+    pub(crate) fn open(line: &str) -> Option<HtmlBlock> {
+        let content = line.trim_start_matches(' ');
+        if line.len() - content.len() > 3 || !content.starts_with('<') {
+            return None;
+        }
+        let content = &content[1..];
+
+        if content.starts_with("!--") {
+            return Some(HtmlBlock::Until("-->".to_owned()));
+        }
+        if content.starts_with("![CDATA[") {
+            return Some(HtmlBlock::Until("]]>".to_owned()));
+        }
+        if content.starts_with('?') {
+            return Some(HtmlBlock::Until("?>".to_owned()));
+        }
+        if content.strip_prefix('!').is_some_and(|rest| rest.starts_with(|c: char| c.is_ascii_alphabetic())) {
+            return Some(HtmlBlock::Until(">".to_owned()));
+        }
+
+        let (closing, tag) = match content.strip_prefix('/') {
+            Some(tag) => (true, tag),
+            None => (false, content),
+        };
+        let name_len = tag.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(tag.len());
+        let (name, after) = (tag[..name_len].to_ascii_lowercase(), &tag[name_len..]);
+        if !closing && RAW_TAGS.contains(&name.as_str()) && (after.is_empty() || after.starts_with([' ', '\t', '>'])) {
+            return Some(HtmlBlock::Until(format!("</{}>", name)));
+        }
+        if BLOCK_TAGS.contains(&name.as_str()) && (after.is_empty() || after.starts_with([' ', '\t', '>']) || after.starts_with("/>")) {
+            return Some(HtmlBlock::UntilBlankLine);
+        }
+        None
+    }
+
+    /// Whether the block ends with `line`, which is still part of it unless the block ends before blank lines
+    pub(crate) fn is_closed_by(&self, line: &str) -> bool {
+        match self {
+            HtmlBlock::Until(end) => line.to_ascii_lowercase().contains(end.as_str()),
+            HtmlBlock::UntilBlankLine => line.trim().is_empty(),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::borrow::Cow;
+use crate::format::find_code_span_end;
+
+/// A run of `*` or `_` that may open or close emphasis
+struct DelimiterRun {
+    start: usize,
+    len: usize,
+    marker: char,
+    prev: Option<char>,
+    next: Option<char>,
+}
+
+impl DelimiterRun {
+    fn can_open(&self, marker: char) -> bool {
+        let (left, right) = flanking(self.prev, self.next);
+        match marker {
+            // Underscores can't open emphasis inside a word, like in `snake_case`
+            '_' => left && (!right || self.prev.is_some_and(is_punctuation)),
+            _ => left,
+        }
+    }
+
+    fn can_close(&self, marker: char) -> bool {
+        let (left, right) = flanking(self.prev, self.next);
+        match marker {
+            '_' => right && (!left || self.next.is_some_and(is_punctuation)),
+            _ => right,
+        }
+    }
+}
+
+/// Rewrites the markers of emphasis (`*a*` or `_a_`) and strong emphasis (`**a**` or `__a__`) to the given characters.
+///
+/// Only pairs of markers on the same line are rewritten, and only if the new markers would still be emphasis,
+/// code spans, autolinks, HTML tags and link destinations are left untouched.
+pub(crate) fn normalize_emphasis(line: &str, emphasis_char: Option<char>, strong_char: Option<char>) -> Cow<'_, str> {
+    let runs = delimiter_runs(line);
+    let mut openers: Vec<&DelimiterRun> = Vec::new();
+    let mut replacements = Vec::new();
+    for run in &runs {
+        let opener_index = if run.can_close(run.marker) {
+            openers.iter().rposition(|opener| opener.marker == run.marker && opener.len == run.len)
+        } else {
+            None
+        };
+        match opener_index {
+            Some(index) => {
+                let opener = openers[index];
+                openers.truncate(index);
+                let target = match run.len {
+                    1 => emphasis_char,
+                    2 => strong_char,
+                    _ => None,
+                };
+                if let Some(target) = target.filter(|&target| target != run.marker) {
+                    // A neighbor that is already the new marker would merge with it
+                    let merges = [opener.prev, opener.next, run.prev, run.next].contains(&Some(target));
+                    if !merges && opener.can_open(target) && run.can_close(target) {
+                        replacements.push((opener.start, run.len, target));
+                        replacements.push((run.start, run.len, target));
+                    }
+                }
+            },
+            None if run.can_open(run.marker) => openers.push(run),
+            None => {},
+        }
+    }
+
+    if replacements.is_empty() {
+        return Cow::Borrowed(line);
+    }
+    replacements.sort_unstable();
+    let mut normalized = String::new();
+    let mut copied = 0;
+    for (start, len, target) in replacements {
+        normalized.push_str(&line[copied..start]);
+        normalized.extend(std::iter::repeat_n(target, len));
+        copied = start + len;
+    }
+    normalized.push_str(&line[copied..]);
+    Cow::Owned(normalized)
+}
+
+fn delimiter_runs(line: &str) -> Vec<DelimiterRun> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while let Some(c) = line[i..].chars().next() {
+        match c {
+            '\\' => i += 1 + line[i+1..].chars().next().map_or(0, char::len_utf8),
+            '`' => {
+                let fence_len = line[i..].bytes().take_while(|&b| b == b'`').count();
+                i += find_code_span_end(&line[i+fence_len..], fence_len).map_or(fence_len, |end| fence_len + end);
+            },
+            '<' if line[i+1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/') => {
+                i += line[i..].find('>').map_or(1, |end| end + 1);
+            },
+            ']' if line[i+1..].starts_with('(') => i += 1 + link_destination_len(&line[i+1..]),
+            '*' | '_' => {
+                let len = line[i..].chars().take_while(|&next| next == c).count();
+                runs.push(DelimiterRun {
+                    start: i,
+                    len,
+                    marker: c,
+                    prev: line[..i].chars().next_back(),
+                    next: line[i+len..].chars().next(),
+                });
+                i += len;
+            },
+            _ => i += c.len_utf8(),
+        }
+    }
+    runs
+}
+
+/// The length of the `(destination "title")` after a link's text, up to its matching parenthesis
+fn link_destination_len(text: &str) -> usize {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            },
+            _ => {},
+        }
+    }
+    1
+}
+
+/// Whether a delimiter run is left-flanking and right-flanking, the start and end of the line count as whitespace
+fn flanking(prev: Option<char>, next: Option<char>) -> (bool, bool) {
+    let prev_space = prev.is_none_or(char::is_whitespace);
+    let next_space = next.is_none_or(char::is_whitespace);
+    let prev_punct = prev.is_some_and(is_punctuation);
+    let next_punct = next.is_some_and(is_punctuation);
+    let left = !next_space && (!next_punct || prev_space || prev_punct);
+    let right = !prev_space && (!prev_punct || next_space || next_punct);
+    (left, right)
+}
+
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation() || (!c.is_ascii() && !c.is_alphanumeric() && !c.is_whitespace())
+}
@@ -0,0 +1,63 @@
+use mdfmt::{ParsedTable, TableAlignment};
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct File<'a> {
+    file: &'a str,
+    tables: Vec<Table<'a>>,
+}
+
+#[derive(Serialize)]
+struct Table<'a> {
+    start_line: usize,
+    end_line: usize,
+    columns: Vec<Column<'a>>,
+}
+
+#[derive(Serialize)]
+struct Column<'a> {
+    alignment: &'static str,
+    header: &'a str,
+    cells: &'a [String],
+}
+
+/// Describes the tables of a file as a single line of JSON, like
+/// `{"file":"a.md","tables":[{"start_line":1,"end_line":3,"columns":[{"alignment":"left","header":"a","cells":["1"]}]}]}`
+pub fn describe_tables(file: &str, tables: &[ParsedTable]) -> String {
+    let tables = tables.iter().map(|table| Table {
+        start_line: table.start_line,
+        end_line: table.end_line,
+        columns: table.columns.iter().map(|column| Column {
+            alignment: match column.alignment {
+                TableAlignment::None => "none",
+                TableAlignment::Left => "left",
+                TableAlignment::Center => "center",
+                TableAlignment::Right => "right",
+            },
+            header: column.lines.first().map_or("", String::as_str),
+            cells: column.lines.get(1..).unwrap_or(&[]),
+        }).collect(),
+    }).collect();
+    let mut json = serde_json::to_string(&File { file, tables }).expect("failed serializing tables");
+    json.push('\n');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_are_described() {
+        let tables = mdfmt::parse_tables("| a | \"b\" |\n|:-|-:|\n| 1 | 2\\\\ |\n", &mdfmt::FormatOptions::default()).unwrap();
+        assert_eq!(describe_tables("dir/a.md", &tables), concat!(
+            r#"{"file":"dir/a.md","tables":[{"start_line":1,"end_line":3,"columns":["#,
+            r#"{"alignment":"left","header":"a","cells":["1"]},"#,
+            r#"{"alignment":"right","header":"\"b\"","cells":["2\\\\"]}]}]}"#, "\n"));
+    }
+
+    #[test]
+    fn no_tables() {
+        assert_eq!(describe_tables("-", &[]), "{\"file\":\"-\",\"tables\":[]}\n");
+    }
+}
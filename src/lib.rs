@@ -0,0 +1,18 @@
+//! Formats the tables in Markdown documents.
+//!
+//! This is the library behind the `mdfmt` binary, for programs that want to format Markdown without shelling out.
+
+mod diagnostic;
+mod error;
+mod footnote;
+mod format;
+mod html;
+mod inline;
+mod options;
+mod text;
+mod width;
+
+pub use diagnostic::{Diagnostic, Severity};
+pub use error::FormatError;
+pub use format::{format_bytes, format_content, format_range, format_to_writer, parse_tables, Formatted, Formatter, ParsedTable, TableAlignment, TableColumn};
+pub use options::{BlockquoteStyle, BulletChar, EmphasisChar, FinalNewline, FormatOptions, HeadingStyle, IndentStyle, LineEnding, OrderedListStyle, PipeStyle, SortBy, SortComparator, ThematicBreak};
@@ -1,64 +1,979 @@
-use std::fs::{File, read_to_string};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, OpenOptions};
 use std::error::Error;
-use std::io::{Write, Read};
+use std::io::{self, BufRead, BufReader, BufWriter, Write, Read};
+use std::path::{Component, Path, PathBuf};
 use std::process::exit;
-use clap::{Arg, App};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use clap::{Arg, App, AppSettings, ArgMatches, ErrorKind, SubCommand};
+use encoding::Encoding;
+use mdfmt::{format_bytes, parse_tables, FormatOptions, Formatted, Formatter, SortBy, ThematicBreak};
 
-mod format;
+mod config;
+mod diff;
+mod encoding;
+mod json;
+mod parallel;
+mod walk;
+
+/// The name of stdin in messages, unless it's given with --stdin-filename
+const STDIN_LABEL: &str = "<stdin>";
+
+/// The exit status when the arguments are invalid or a file couldn't be read, formatted or written.
+/// Formatting changes found by --diff or --list-changed exit with 1, so this must be another status.
+const FAILURE_EXIT: i32 = 2;
+
+/// The number of temporary files created by `write_atomically`, which is part of their names
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+enum Output<'a> {
+    Stdout,
+    File(&'a OsStr),
+    Dir(&'a Path),
+    InPlace(Option<Backup<'a>>),
+    Diff,
+    /// With the separator written after each path
+    ListChanged(u8),
+    Json,
+}
+
+/// Where the original content of a file formatted in place is saved, next to it
+struct Backup<'a> {
+    suffix: &'a OsStr,
+    overwrite: bool,
+}
+
+/// What formatting a file printed, kept until the files before it are done so the messages stay in order
+struct Report {
+    changed: bool,
+    output: Vec<u8>,
+    messages: String,
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let args = App::new("mdfmt")
+    let print0 = Arg::with_name("print0")
+        .long("print0")
+        .help("Separate the paths of the changed files with NUL bytes instead of newlines, for 'xargs -0'");
+    let app = App::new("mdfmt")
+        .version(env!("CARGO_PKG_VERSION"))
         .about("Markdown Formatter")
-        .arg(Arg::with_name("inplace")
-            .short("i")
-            .long("in-place")
-            .help("Modify input file in place"))
-        .arg(Arg::with_name("strict")
-            .short("s")
-            .long("strict")
-            .help("Warn if an input file contains broken tables (instead of ignoring them)"))
-        .arg(Arg::with_name("source")
-            .help("The source file to format")
-            .index(1))
-        .arg(Arg::with_name("destination")
-            .help("Ouptut file (if not inplace)")
-            .index(2))
-        .get_matches();
-
-    let strict = args.is_present("strict");
+        // So that a file can be named like a subcommand when it comes after an option (e.g. `mdfmt -i check`)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .args(&common_args())
+        .args(&output_args())
+        .arg(Arg::with_name("diff")
+            .long("diff")
+            .help("Print a diff of the changes instead of writing them, and fail if there are any"))
+        .arg(Arg::with_name("list-changed")
+            .long("list-changed")
+            .help("Only print the paths of the files that formatting would change, one per line, and fail if there are any")
+            .conflicts_with_all(&["inplace", "diff", "output-dir", "stream", "json"]))
+        .arg(print0.clone().requires("list-changed"))
+        .subcommand(SubCommand::with_name("fmt")
+            .about("Format the files, like when no subcommand is given")
+            .args(&common_args())
+            .args(&output_args()))
+        .subcommand(SubCommand::with_name("check")
+            .about("Only print the paths of the files that formatting would change, and fail if there are any (like --list-changed)")
+            .args(&common_args())
+            .arg(print0))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Print a diff of the changes instead of writing them, and fail if there are any (like --diff)")
+            .args(&common_args()));
+    let matches = app.get_matches_safe().unwrap_or_else(|err| match err.kind {
+        ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => err.exit(),
+        _ => {
+            eprintln!("{}", err.message);
+            exit(FAILURE_EXIT);
+        },
+    });
+    let (subcommand, args) = match matches.subcommand() {
+        (name, Some(sub_matches)) => (name, sub_matches),
+        _ => ("fmt", &matches),
+    };
+
     let inplace = args.is_present("inplace");
-    if inplace && args.is_present("destination") {
-        eprintln!("Cannot be both inplace and have a destination.");
-        exit(1);
+    let show_diff = subcommand == "diff" || args.is_present("diff");
+    let list_changed = subcommand == "check" || args.is_present("list-changed");
+    let stream = args.is_present("stream");
+    let encoding = args.value_of("encoding").map_or(Encoding::UTF_8, |encoding| encoding.parse().unwrap());
+    if inplace && show_diff {
+        eprintln!("Cannot print a diff while writing the output.");
+        exit(FAILURE_EXIT);
+    }
+    if stream && (inplace || show_diff) {
+        eprintln!("Cannot stream while formatting in place or printing a diff.");
+        exit(FAILURE_EXIT);
+    }
+
+    let mut files = args.values_of_os("files").map_or_else(Vec::new, |files| files.collect::<Vec<_>>());
+    if args.is_present("stdin") {
+        files.insert(0, OsStr::new("-"));
     }
+    let output = if inplace {
+        Output::InPlace(args.is_present("backup").then(|| Backup {
+            suffix: args.value_of_os("backup").unwrap_or_else(|| OsStr::new(".bak")),
+            overwrite: args.is_present("force"),
+        }))
+    } else if show_diff {
+        Output::Diff
+    } else if list_changed {
+        Output::ListChanged(if args.is_present("print0") { b'\0' } else { b'\n' })
+    } else if args.is_present("json") {
+        Output::Json
+    } else if let Some(dir) = args.value_of_os("output-dir") {
+        Output::Dir(Path::new(dir))
+    } else if files.len() == 2 && is_single_file(Path::new(files[0])) && !args.is_present("stdout")
+        && !args.is_present("files-from") && !args.is_present("files-from0") {
+        Output::File(files.pop().unwrap())
+    } else {
+        Output::Stdout
+    };
 
-    let filepath = match args.value_of_os("source") {
-        Some(source) if source == "-" => None,
-        source => source
+    let file_list = match (args.value_of_os("files-from"), args.value_of_os("files-from0")) {
+        (Some(list), _) => Some((Path::new(list), b'\n')),
+        (_, Some(list)) => Some((Path::new(list), 0)),
+        (None, None) => None,
     };
+    let mut listed_files = Vec::new();
+    if let Some((list, separator)) = file_list {
+        match walk::read_file_list(list, separator) {
+            Ok(list_files) => listed_files = list_files,
+            Err(err) => {
+                eprintln!("{}: {}", list.display(), err);
+                exit(FAILURE_EXIT);
+            },
+        }
+    }
 
-    let input_content = if let Some(filepath) = filepath {
-        read_to_string(filepath)?
-    } else if inplace {
+    let mut failures = Vec::new();
+    let mut sources = Vec::new();
+    if files.is_empty() && file_list.is_none() {
+        sources.push(None);
+    }
+    for path in files.iter().map(Path::new).chain(listed_files.iter().map(PathBuf::as_path)) {
+        if path == Path::new("-") {
+            sources.push(None);
+        } else if path.is_dir() {
+            let mut dir_files = Vec::new();
+            walk::collect_markdown_files(path, args.is_present("recursive"), &mut dir_files, &mut failures);
+            sources.extend(dir_files.into_iter().map(Some));
+        } else if !path.exists() && walk::is_glob(path.as_os_str()) {
+            let mut matched_files = Vec::new();
+            walk::expand_glob(&path.to_string_lossy(), &mut matched_files, &mut failures);
+            sources.extend(matched_files.into_iter().map(Some));
+        } else {
+            sources.push(Some(path.to_owned()));
+        }
+    }
+    if file_list.is_some_and(|(list, _)| list == Path::new("-")) && sources.contains(&None) {
+        eprintln!("Cannot format stdin while reading the list of files from it.");
+        exit(FAILURE_EXIT);
+    }
+    if inplace && sources.contains(&None) {
         eprintln!("Cannot be inplace while reading from stdin");
+        exit(FAILURE_EXIT);
+    }
+    if matches!(output, Output::Dir(_)) && sources.contains(&None) && !args.is_present("stdin-filename") {
+        eprintln!("Cannot write stdin to the output directory without --stdin-filename.");
+        exit(FAILURE_EXIT);
+    }
+    if sources.len() > 1 && matches!(output, Output::Stdout | Output::File(_)) {
+        eprintln!("Cannot format multiple files unless inplace.");
+        exit(FAILURE_EXIT);
+    }
+
+    let stdin_path = args.value_of_os("stdin-filename").map(Path::new);
+    let mut configs = match args.value_of_os("config") {
+        Some(path) => config::Configs::from_file(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            exit(FAILURE_EXIT);
+        }),
+        None => config::Configs::default(),
+    };
+    let mut jobs = sources.into_iter().map(|source| {
+        let options = configs.options_for(source.as_deref().or(stdin_path))
+            .map(|options| apply_args(options, args))
+            .map_err(|err| err.to_string());
+        (source, options)
+    }).collect::<Vec<_>>();
+    let fail_fast = args.is_present("fail-fast");
+    if fail_fast && !failures.is_empty() {
+        jobs.clear();
+    }
+    let job_count = args.value_of("jobs").map_or_else(parallel::default_jobs, |jobs| jobs.parse().unwrap());
+    // With --fail-fast, the files after the first one that failed so far are skipped, but never the ones before it:
+    // they are reported first, since the reports are in order
+    let first_failure = AtomicUsize::new(usize::MAX);
+    let indexed_jobs = jobs.iter().enumerate().collect::<Vec<_>>();
+    let reports = parallel::map(&indexed_jobs, job_count, |&(index, (source, options))| {
+        if index > first_failure.load(Ordering::Relaxed) {
+            return None;
+        }
+        let report = options.as_ref().map_err(Clone::clone).and_then(|options| if stream {
+            stream_file(source.as_deref(), stdin_path, &output, options, encoding)
+        } else {
+            format_file(source.as_deref(), stdin_path, &output, options, encoding)
+        }.map_err(|err| err.to_string()));
+        if report.is_err() && fail_fast {
+            first_failure.fetch_min(index, Ordering::Relaxed);
+        }
+        Some(report)
+    });
+    drop(indexed_jobs);
+
+    let verbose = args.is_present("verbose");
+    let mut changed = false;
+    let (mut formatted, mut unchanged) = (0, 0);
+    for ((source, _), report) in jobs.into_iter().zip(reports) {
+        // Only the files after the first failure are skipped, and the loop stops at that failure
+        let report = report.expect("skipped a file before the first failure");
+        let failed = report.is_err();
+        match report {
+            Ok(report) => {
+                if !args.is_present("quiet") {
+                    eprint!("{}", report.messages);
+                }
+                // Streaming doesn't know if the content changed
+                let action = if report.changed || stream {
+                    formatted += 1;
+                    "formatted"
+                } else {
+                    unchanged += 1;
+                    "unchanged"
+                };
+                if verbose {
+                    eprintln!("{} {}", action, source_label(source.as_deref(), stdin_path));
+                }
+                if let Err(err) = quit_on_broken_pipe(io::stdout().write_all(&report.output)) {
+                    eprintln!("Failed printing to stdout: {}", err);
+                    exit(FAILURE_EXIT);
+                }
+                changed |= report.changed;
+            },
+            Err(err) => {
+                if verbose {
+                    eprintln!("skipped {}", source_label(source.as_deref(), stdin_path));
+                }
+                failures.push((source.unwrap_or_else(|| stdin_path.unwrap_or_else(|| Path::new(STDIN_LABEL)).to_owned()), err.into()));
+            },
+        }
+        if failed && fail_fast {
+            break;
+        }
+    }
+
+    let failed = !failures.is_empty();
+    let errors = failures.len();
+    for (path, err) in failures {
+        eprintln!("{}: {}", path.display(), err);
+    }
+    if args.is_present("summary") && !args.is_present("quiet") {
+        eprintln!("{}", summary(formatted, unchanged, errors));
+    }
+
+    if failed {
+        exit(FAILURE_EXIT);
+    }
+    if (show_diff || list_changed) && changed {
         exit(1);
+    }
+    Ok(())
+}
+
+/// The options of every subcommand, which set which files are formatted and how
+fn common_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("strict")
+            .short("s")
+            .long("strict")
+            .help("Warn if an input file contains broken tables (instead of ignoring them), with --strict=lint also report the tables that were reformatted")
+            .takes_value(true)
+            .min_values(0)
+            .require_equals(true)
+            .possible_values(&["lint"]),
+        Arg::with_name("quiet")
+            .short("q")
+            .long("quiet")
+            .help("Don't print the warnings about the input, the exit status is the same"),
+        Arg::with_name("verbose")
+            .short("v")
+            .long("verbose")
+            .help("Print on stderr whether each file was formatted, unchanged or skipped")
+            .conflicts_with("quiet"),
+        Arg::with_name("summary")
+            .long("summary")
+            .help("Print on stderr how many files were formatted, unchanged or skipped, at the end of the run"),
+        Arg::with_name("fail-fast")
+            .long("fail-fast")
+            .help("Stop at the first file that can't be read, formatted or written, instead of reporting every failure at the end")
+            .overrides_with("no-fail-fast"),
+        Arg::with_name("no-fail-fast")
+            .long("no-fail-fast")
+            .help("Format every file even if some fail, and report the failures at the end (the default)")
+            .overrides_with("fail-fast"),
+        Arg::with_name("line-ending")
+            .long("line-ending")
+            .help("The line endings of the output, by default the most common ones in each input file")
+            .takes_value(true)
+            .possible_values(&["auto", "lf", "crlf"]),
+        Arg::with_name("final-newline")
+            .long("final-newline")
+            .help("End the output with a newline even if the input didn't have one, like --eol-at-eof ensure")
+            .conflicts_with("eol-at-eof"),
+        Arg::with_name("eol-at-eof")
+            .long("eol-at-eof")
+            .help("Whether to end the output with a newline: always, never, or only if the input did (preserve by default)")
+            .takes_value(true)
+            .possible_values(&["ensure", "strip", "preserve"]),
+        Arg::with_name("config")
+            .long("config")
+            .help("Load the options from this configuration file for every input, instead of the nearest .mdfmt.toml")
+            .takes_value(true)
+            .value_name("PATH"),
+        Arg::with_name("encoding")
+            .long("encoding")
+            .help("The encoding the files are read and written in, like 'utf-8' (the default), 'latin-1' or 'shift_jis'")
+            .takes_value(true)
+            .value_name("ENCODING")
+            .validator(is_encoding),
+        Arg::with_name("keep-bom")
+            .long("keep-bom")
+            .help("Keep the UTF-8 byte order mark at the start of the input files, instead of removing it"),
+        Arg::with_name("tab-width")
+            .long("tab-width")
+            .help("How many columns a tab stop spans, tabs inside table cells are expanded to spaces")
+            .takes_value(true)
+            .value_name("N")
+            .validator(is_number),
+        Arg::with_name("no-table")
+            .long("no-table")
+            .help("Leave the tables as they are, and only format the rest of the documents"),
+        Arg::with_name("collapse-spaces")
+            .long("collapse-spaces")
+            .help("Collapse the runs of spaces inside table cells to one space, except in code spans"),
+        Arg::with_name("loose-pipes")
+            .long("loose-pipes")
+            .help("Also format tables whose rows don't start and end with a pipe"),
+        Arg::with_name("strict-cell-count")
+            .long("strict-cell-count")
+            .help("Leave the tables with rows that have fewer or more cells than their header as they are, instead of filling or cutting these rows"),
+        Arg::with_name("compact")
+            .long("compact")
+            .help("Write tables without padding or aligning their cells"),
+        Arg::with_name("short-delimiters")
+            .long("short-delimiters")
+            .help("Write the delimiter rows of tables as '---' or ':-:' instead of making them as wide as the columns"),
+        Arg::with_name("max-col-width")
+            .long("max-col-width")
+            .help("Wrap the table cells wider than this on continuation rows")
+            .takes_value(true)
+            .value_name("N")
+            .validator(is_number),
+        Arg::with_name("warn-table-width")
+            .long("warn-table-width")
+            .help("With --strict=lint, also report the tables wider than this many columns once formatted")
+            .takes_value(true)
+            .value_name("N")
+            .validator(is_number),
+        Arg::with_name("min-width")
+            .long("min-width")
+            .help("Pad every table column to at least this width")
+            .takes_value(true)
+            .value_name("N")
+            .validator(is_number),
+        Arg::with_name("keep-trailing-whitespace")
+            .long("keep-trailing-whitespace")
+            .help("Don't remove trailing whitespace outside of tables and code blocks"),
+        Arg::with_name("max-blank-lines")
+            .long("max-blank-lines")
+            .help("Collapse runs of blank lines outside of code blocks to at most this many lines (1 by default)")
+            .takes_value(true)
+            .value_name("N")
+            .validator(is_number),
+        Arg::with_name("bullet")
+            .long("bullet")
+            .help("The marker of unordered list items, or 'preserve' to leave them as they are (- by default)")
+            .takes_value(true)
+            .possible_values(&["-", "*", "+", "preserve"]),
+        Arg::with_name("thematic-break")
+            .long("thematic-break")
+            .help("How to write horizontal rules, like '---' or '* * *', or 'preserve' to leave them as they are (preserve by default)")
+            .takes_value(true)
+            .value_name("RULE")
+            .validator(is_thematic_break),
+        Arg::with_name("emphasis")
+            .long("emphasis")
+            .help("The marker of emphasis like *this*, or 'preserve' to leave them as they are (preserve by default)")
+            .takes_value(true)
+            .possible_values(&["*", "_", "preserve"]),
+        Arg::with_name("strong")
+            .long("strong")
+            .help("The marker of strong emphasis like **this**, or 'preserve' to leave them as they are (preserve by default)")
+            .takes_value(true)
+            .possible_values(&["*", "_", "preserve"]),
+        Arg::with_name("link-definitions")
+            .long("link-definitions")
+            .help("Rewrite link reference definitions like '[label]: url \"title\"' with one space after the colon and the title between double quotes"),
+        Arg::with_name("lowercase-labels")
+            .long("lowercase-labels")
+            .help("Also lowercase the labels of link reference definitions, which are matched without case")
+            .requires("link-definitions"),
+        Arg::with_name("renumber-footnotes")
+            .long("renumber-footnotes")
+            .help("Renumber the footnotes with numeric labels like [^3] from 1, in the order they are first referenced"),
+        Arg::with_name("ordered-lists")
+            .long("ordered-lists")
+            .help("How to number the items of ordered lists (preserve by default)")
+            .takes_value(true)
+            .possible_values(&["preserve", "sequential", "lazy"]),
+        Arg::with_name("strip-closing-hashes")
+            .long("strip-closing-hashes")
+            .help("Remove the optional closing # of headings like '## Title ##'"),
+        Arg::with_name("fix-heading-space")
+            .long("fix-heading-space")
+            .help("Add the space missing after the # of lines like '#Title' to make them headings, instead of leaving them as text like '#hashtag'"),
+        Arg::with_name("indent-style")
+            .long("indent-style")
+            .help("Rewrite the indentation of text lines with spaces or tabs of --tab-width columns (preserve by default)")
+            .takes_value(true)
+            .possible_values(&["preserve", "spaces", "tabs"]),
+        Arg::with_name("headings")
+            .long("headings")
+            .help("How to write level 1 and 2 headings (preserve by default)")
+            .takes_value(true)
+            .possible_values(&["preserve", "atx", "setext"]),
+        Arg::with_name("blockquotes")
+            .long("blockquotes")
+            .help("How to write the > markers of block quotes, 'spaced' like '> > text' or 'compact' like '>> text' (preserve by default)")
+            .takes_value(true)
+            .possible_values(&["preserve", "spaced", "compact"]),
+        Arg::with_name("align-numbers")
+            .long("align-numbers")
+            .help("Right-align the table columns that only contain numbers, unless they already have an alignment"),
+        Arg::with_name("align-decimal")
+            .long("align-decimal")
+            .help("Line up the decimal points of the numbers in the table columns that only contain numbers"),
+        Arg::with_name("justify")
+            .long("justify")
+            .help("Pad the table cells according to the alignment of their column, instead of always aligning them left"),
+        Arg::with_name("ascii-safe")
+            .long("ascii-safe")
+            .help("Write the delimiter rows of tables with only '-' and '|', dropping the colons that align their columns"),
+        Arg::with_name("unicode-dashes")
+            .long("unicode-dashes")
+            .help("Accept en dashes, em dashes and other unicode dashes in delimiter rows, and write them as '-'"),
+        Arg::with_name("normalize-captions")
+            .long("normalize-captions")
+            .help("Write the captions of tables like 'Table: Sales' with a single space after 'Table:'"),
+        Arg::with_name("pipes")
+            .long("pipes")
+            .help("Where to put spaces between the pipes of tables and their content, 'tight-outer' like '|a | b|' or 'tight-inner' like '| a|b |' (padded by default)")
+            .takes_value(true)
+            .possible_values(&["padded", "tight-outer", "tight-inner"]),
+        Arg::with_name("no-outer-pipes")
+            .long("no-outer-pipes")
+            .help("Write tables without the pipes at the start and end of their rows, like 'a | b', unless they wouldn't read back the same"),
+        Arg::with_name("sort-by")
+            .long("sort-by")
+            .help("Sort the body rows of tables by this column, counted from 0, optionally followed by ':desc' and ':lexical' or ':numeric' to override how the cells are compared (e.g. '2:desc:lexical')")
+            .takes_value(true)
+            .value_name("COL[:asc|desc][:auto|lexical|numeric]")
+            .validator(is_sort_by),
+        Arg::with_name("print-width")
+            .long("print-width")
+            .help("Wrap the lines of plain paragraphs at this many columns, which loses their meaningful line breaks")
+            .takes_value(true)
+            .value_name("N")
+            .validator(is_number),
+        Arg::with_name("fence-length")
+            .long("fence-length")
+            .help("Rewrite the fences of code blocks to be this long, or longer if their content needs it")
+            .takes_value(true)
+            .value_name("N")
+            .validator(is_number),
+        Arg::with_name("stdin")
+            .long("stdin")
+            .help("Format stdin, like giving '-' as the first file")
+            .conflicts_with("inplace"),
+        Arg::with_name("stdin-filename")
+            .long("stdin-filename")
+            .help("The path of the file read from stdin, to use in messages")
+            .takes_value(true)
+            .value_name("PATH"),
+        Arg::with_name("recursive")
+            .short("r")
+            .long("recursive")
+            .help("Also format the Markdown files in subdirectories of the given directories"),
+        Arg::with_name("files-from")
+            .long("files-from")
+            .help("Also format the files listed in this file, one per line, or in stdin if it is '-'")
+            .takes_value(true)
+            .value_name("PATH")
+            .conflicts_with("files-from0"),
+        Arg::with_name("files-from0")
+            .long("files-from0")
+            .help("Like --files-from, but the paths are separated by NUL bytes (like the output of 'find -print0')")
+            .takes_value(true)
+            .value_name("PATH"),
+        Arg::with_name("jobs")
+            .short("j")
+            .long("jobs")
+            .help("How many files to format at the same time (the number of CPUs by default)")
+            .takes_value(true)
+            .value_name("N")
+            .validator(is_number),
+        Arg::with_name("files")
+            .help("The source files or directories to format, or a single source file followed by its output file (if not inplace)")
+            .value_name("FILE")
+            .multiple(true)
+            .index(1),
+    ]
+}
+
+/// The options of the `fmt` subcommand that choose where the formatted files are written
+fn output_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("inplace")
+            .short("i")
+            .long("in-place")
+            .help("Modify input files in place"),
+        Arg::with_name("backup")
+            .long("backup")
+            .help("Save the original content of the files formatted in place next to them, with this suffix (.bak by default)")
+            .takes_value(true)
+            .value_name("SUFFIX")
+            .min_values(0)
+            .require_equals(true)
+            .requires("inplace"),
+        Arg::with_name("force")
+            .long("force")
+            .help("Overwrite the backups that already exist, instead of failing")
+            .requires("backup"),
+        Arg::with_name("json")
+            .long("json")
+            .help("Print the tables of each file as a line of JSON (their position, and the alignment and cells of their columns) instead of formatting it")
+            .conflicts_with_all(&["inplace", "diff", "output-dir", "stream"]),
+        Arg::with_name("stdout")
+            .long("stdout")
+            .help("Write the formatted file to stdout, so that a second file is never taken as the output file")
+            .conflicts_with_all(&["inplace", "diff", "output-dir", "list-changed", "json"]),
+        Arg::with_name("output-dir")
+            .short("o")
+            .long("output-dir")
+            .help("Write each formatted file to the same path under this directory, instead of stdout")
+            .takes_value(true)
+            .value_name("DIR")
+            .conflicts_with_all(&["inplace", "diff"]),
+        Arg::with_name("stream")
+            .long("stream")
+            .help("Read and write the input line by line, to format very large files with little memory (line endings default to those of the first line)"),
+    ]
+}
+
+/// Overrides the options of the configuration file with the flags given on the command line
+fn apply_args(mut options: FormatOptions, args: &ArgMatches) -> FormatOptions {
+    if args.is_present("strict") {
+        options = options.strict(true);
+    }
+    if args.value_of("strict") == Some("lint") {
+        options = options.lint_tables(true);
+    }
+    if let Some(line_ending) = args.value_of("line-ending") {
+        options = options.line_ending(line_ending.parse().unwrap());
+    }
+    if args.is_present("no-table") {
+        options = options.format_tables(false);
+    }
+    if args.is_present("collapse-spaces") {
+        options = options.collapse_cell_spaces(true);
+    }
+    if args.is_present("final-newline") {
+        options = options.ensure_final_newline(true);
+    }
+    if let Some(final_newline) = args.value_of("eol-at-eof") {
+        options = options.final_newline(final_newline.parse().unwrap());
+    }
+    if args.is_present("keep-bom") {
+        options = options.keep_bom(true);
+    }
+    if let Some(tab_width) = args.value_of("tab-width") {
+        options = options.tab_width(tab_width.parse().unwrap());
+    }
+    if args.is_present("loose-pipes") {
+        options = options.allow_loose_pipes(true);
+    }
+    if args.is_present("strict-cell-count") {
+        options = options.strict_cell_count(true);
+    }
+    if args.is_present("compact") {
+        options = options.compact(true);
+    }
+    if args.is_present("justify") {
+        options = options.justify_cells(true);
+    }
+    if args.is_present("ascii-safe") {
+        options = options.ascii_safe(true);
+    }
+    if args.is_present("unicode-dashes") {
+        options = options.unicode_dashes(true);
+    }
+    if args.is_present("normalize-captions") {
+        options = options.normalize_table_captions(true);
+    }
+    if let Some(sort_by) = args.value_of("sort-by") {
+        options = options.sort_by(Some(sort_by.parse().unwrap()));
+    }
+    if let Some(style) = args.value_of("pipes") {
+        options = options.pipe_style(style.parse().unwrap());
+    }
+    if args.is_present("no-outer-pipes") {
+        options = options.outer_pipes(false);
+    }
+    if args.is_present("short-delimiters") {
+        options = options.fit_delimiter_width(false);
+    }
+    if let Some(max_col_width) = args.value_of("max-col-width") {
+        options = options.max_col_width(Some(max_col_width.parse().unwrap()));
+    }
+    if let Some(width) = args.value_of("warn-table-width") {
+        options = options.warn_table_width(Some(width.parse().unwrap()));
+    }
+    if let Some(min_width) = args.value_of("min-width") {
+        options = options.min_col_width(min_width.parse().unwrap());
+    }
+    if args.is_present("keep-trailing-whitespace") {
+        options = options.trim_trailing_whitespace(false);
+    }
+    if let Some(max_blank_lines) = args.value_of("max-blank-lines") {
+        options = options.max_blank_lines(Some(max_blank_lines.parse().unwrap()));
+    }
+    match args.value_of("bullet") {
+        Some("preserve") => options = options.bullet_char(None),
+        Some(bullet) => options = options.bullet_char(Some(bullet.parse().unwrap())),
+        None => {},
+    }
+    match args.value_of("thematic-break") {
+        Some("preserve") => options = options.thematic_break(None),
+        Some(rule) => options = options.thematic_break(Some(rule.parse().unwrap())),
+        None => {},
+    }
+    if let Some(marker) = args.value_of("emphasis") {
+        options = options.emphasis_char(Some(marker).filter(|&marker| marker != "preserve").map(|marker| marker.parse().unwrap()));
+    }
+    if let Some(marker) = args.value_of("strong") {
+        options = options.strong_char(Some(marker).filter(|&marker| marker != "preserve").map(|marker| marker.parse().unwrap()));
+    }
+    if args.is_present("link-definitions") {
+        options = options.normalize_link_definitions(true);
+    }
+    if args.is_present("lowercase-labels") {
+        options = options.lowercase_link_labels(true);
+    }
+    if args.is_present("renumber-footnotes") {
+        options = options.renumber_footnotes(true);
+    }
+    if let Some(style) = args.value_of("ordered-lists") {
+        options = options.ordered_list_style(style.parse().unwrap());
+    }
+    if args.is_present("strip-closing-hashes") {
+        options = options.atx_closed_headings(false);
+    }
+    if args.is_present("fix-heading-space") {
+        options = options.fix_heading_space(true);
+    }
+    if let Some(style) = args.value_of("indent-style") {
+        options = options.indent_style(style.parse().unwrap());
+    }
+    if let Some(style) = args.value_of("headings") {
+        options = options.heading_style(style.parse().unwrap());
+    }
+    if let Some(style) = args.value_of("blockquotes") {
+        options = options.blockquote_style(style.parse().unwrap());
+    }
+    if args.is_present("align-numbers") {
+        options = options.auto_number_align(true);
+    }
+    if args.is_present("align-decimal") {
+        options = options.align_decimal(true);
+    }
+    if let Some(width) = args.value_of("print-width") {
+        options = options.print_width(Some(width.parse().unwrap()));
+    }
+    if let Some(len) = args.value_of("fence-length") {
+        options = options.normalize_code_fence(Some(len.parse().unwrap()));
+    }
+    options
+}
+
+/// Formats a single source (or stdin), and returns what should be printed and whether formatting changed its content
+fn format_file(source: Option<&Path>, stdin_path: Option<&Path>, output: &Output, options: &FormatOptions, encoding: Encoding) -> Result<Report, Box<dyn Error>> {
+    let input = if let Some(filepath) = source {
+        fs::read(filepath)?
     } else {
-        let mut input = String::new();
-        std::io::stdin().read_to_string(&mut input)?;
+        let mut input = Vec::new();
+        std::io::stdin().read_to_end(&mut input)?;
         input
     };
 
-    let formatted = format::format_content(&input_content, strict)?;
+    let decoded = encoding.decode(&input)?;
+    let Formatted { content: formatted, diagnostics } = format_bytes(&decoded, options)?;
+    // The input was checked when formatting it, so this borrows it without replacing anything
+    let input_content = String::from_utf8_lossy(&decoded);
+    let messages = diagnostics.iter()
+        .map(|diagnostic| format!("{}:{}\n", source_label(source, stdin_path), diagnostic))
+        .collect();
 
-    if inplace {
-        let mut out_file = File::create(filepath.unwrap())?;
-        out_file.write_all(formatted.as_bytes())?;
-    } else if let Some(destination) = args.value_of_os("destination") {
-        let mut out_file = File::create(destination)?;
-        out_file.write_all(formatted.as_bytes())?;
-    } else {
-        println!("{}", formatted);
+    let changed = formatted != input_content;
+    // Diffs, lists and JSON are printed in UTF-8 like the other messages, only the formatted documents are encoded back
+    let printed = match *output {
+        Output::Diff => diff::unified_diff(&input_content, &formatted, &source_label(source, stdin_path)).into_bytes(),
+        Output::ListChanged(separator) if changed => {
+            let mut listed = source_bytes(source, stdin_path);
+            listed.push(separator);
+            listed
+        },
+        Output::ListChanged(_) => Vec::new(),
+        Output::InPlace(ref backup) => {
+            let formatted = encoding.encode(&formatted)?;
+            if let Some(backup) = backup {
+                write_backup(source.unwrap(), &input, backup)?;
+            }
+            write_atomically(source.unwrap(), &formatted)?;
+            Vec::new()
+        },
+        Output::File(destination) => {
+            let formatted = encoding.encode(&formatted)?;
+            let mut out_file = File::create(destination)?;
+            out_file.write_all(&formatted)?;
+            Vec::new()
+        },
+        Output::Dir(dir) => {
+            let formatted = encoding.encode(&formatted)?;
+            let mut out_file = create_mirrored_file(dir, source.or(stdin_path).unwrap_or_else(|| Path::new(STDIN_LABEL)))?;
+            out_file.write_all(&formatted)?;
+            Vec::new()
+        },
+        Output::Stdout => encoding.encode(&formatted)?.into_owned(),
+        Output::Json => json::describe_tables(&source_label(source, stdin_path), &parse_tables(&input_content, options)?).into_bytes(),
+    };
+
+    Ok(Report { changed, output: printed, messages })
+}
+
+/// Formats a single source (or stdin) to a file or stdout line by line, without holding it in memory.
+/// Whether the content changed isn't known, since it only matters for diffs.
+fn stream_file(source: Option<&Path>, stdin_path: Option<&Path>, output: &Output, options: &FormatOptions, encoding: Encoding) -> Result<Report, Box<dyn Error>> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut reader: Box<dyn BufRead> = match source {
+        Some(filepath) => Box::new(BufReader::new(File::open(filepath)?)),
+        None => Box::new(stdin.lock()),
+    };
+    let mut writer: Box<dyn Write> = match *output {
+        Output::File(destination) => Box::new(BufWriter::new(File::create(destination)?)),
+        Output::Dir(dir) => Box::new(BufWriter::new(create_mirrored_file(dir, source.or(stdin_path).unwrap_or_else(|| Path::new(STDIN_LABEL)))?)),
+        _ => Box::new(stdout.lock()),
+    };
+
+    let mut formatter = Formatter::new(options);
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        formatter.push_line_bytes(&encoding.decode(&line)?)?;
+        quit_on_broken_pipe(writer.write_all(&encoding.encode(&formatter.take_output())?))?;
+        line.clear();
     }
+    let Formatted { content, diagnostics } = formatter.finish();
+    quit_on_broken_pipe(writer.write_all(&encoding.encode(&content)?))?;
+    quit_on_broken_pipe(writer.flush())?;
 
-    Ok(())
+    let messages = diagnostics.iter()
+        .map(|diagnostic| format!("{}:{}\n", source_label(source, stdin_path), diagnostic))
+        .collect();
+    Ok(Report { changed: false, output: Vec::new(), messages })
+}
+
+/// Exits quietly if the output was closed before being fully written, like when piping to `head`,
+/// since nothing else needs to be printed. Other errors are returned.
+fn quit_on_broken_pipe(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => exit(0),
+        result => result,
+    }
+}
+
+/// Creates the file at the path of `source` under `dir`, and the directories it needs
+fn create_mirrored_file(dir: &Path, source: &Path) -> io::Result<File> {
+    let mut destination = dir.to_owned();
+    for component in source.components() {
+        match component {
+            Component::Normal(part) => destination.push(part),
+            Component::ParentDir => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Cannot write a path containing '..' under the output directory"));
+            },
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {},
+        }
+    }
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    File::create(destination)
+}
+
+/// Saves the original `content` of `path` to the same path with the suffix of the backup, keeping its permissions
+fn write_backup(path: &Path, content: &[u8], backup: &Backup) -> io::Result<()> {
+    let mut backup_name = path.file_name().unwrap_or_default().to_owned();
+    backup_name.push(backup.suffix);
+    let backup_path = path.with_file_name(backup_name);
+    let mut backup_file = OpenOptions::new()
+        .write(true)
+        .create_new(!backup.overwrite)
+        .create(backup.overwrite)
+        .truncate(backup.overwrite)
+        .open(&backup_path)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => io::Error::new(err.kind(), format!("The backup {} already exists (use --force to overwrite it)", backup_path.display())),
+            _ => err,
+        })?;
+    backup_file.write_all(content)?;
+    backup_file.set_permissions(fs::metadata(path)?.permissions())
+}
+
+/// Replaces the content of `path` without ever leaving it truncated, by writing to a temporary file and renaming it over the original.
+/// A symlink is followed, so the file it points to is replaced and the link itself is kept.
+fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    write_atomically_with(path, |file| file.write_all(content))
+}
+
+/// Like `write_atomically`, with `write` filling the temporary file
+fn write_atomically_with(path: &Path, write: impl FnOnce(&mut File) -> io::Result<()>) -> io::Result<()> {
+    let path = fs::canonicalize(path)?;
+    let metadata = fs::metadata(&path)?;
+    // Built as an OsString, so that files whose names aren't valid UTF-8 can't end up with the same temporary file.
+    // The counter keeps the files written at the same time by different threads apart.
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(format!(".mdfmt-{}-{}.tmp", std::process::id(), TEMP_FILES.fetch_add(1, Ordering::Relaxed)));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut temp_file = OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        write(&mut temp_file)?;
+        temp_file.sync_all()?;
+        temp_file.set_permissions(metadata.permissions())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Only root can give away a file, so failing to keep the owner is not an error
+            let _ = std::os::unix::fs::fchown(&temp_file, Some(metadata.uid()), Some(metadata.gid()));
+        }
+        fs::rename(&temp_path, &path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn is_number(value: String) -> Result<(), String> {
+    value.parse::<usize>().map(|_| ()).map_err(|_| format!("'{}' is not a valid number", value))
+}
+
+fn is_encoding(value: String) -> Result<(), String> {
+    value.parse::<Encoding>().map(|_| ())
+}
+
+fn is_sort_by(value: String) -> Result<(), String> {
+    value.parse::<SortBy>().map(|_| ())
+}
+
+fn is_thematic_break(value: String) -> Result<(), String> {
+    if value == "preserve" {
+        return Ok(());
+    }
+    value.parse::<ThematicBreak>().map(|_| ())
+}
+
+/// The line printed by --summary, like "3 files formatted, 5 unchanged, 1 error"
+fn summary(formatted: usize, unchanged: usize, errors: usize) -> String {
+    format!("{} {} formatted, {} unchanged, {} {}", formatted, if formatted == 1 { "file" } else { "files" },
+            unchanged, errors, if errors == 1 { "error" } else { "errors" })
+}
+
+/// Whether an input names a single file or stdin, rather than a directory or a glob pattern,
+/// so that the next positional argument can be the file it is written to
+fn is_single_file(path: &Path) -> bool {
+    path == Path::new("-") || !path.is_dir() && (path.exists() || !walk::is_glob(path.as_os_str()))
+}
+
+fn source_label(source: Option<&Path>, stdin_path: Option<&Path>) -> String {
+    source.or(stdin_path).map_or_else(|| STDIN_LABEL.to_owned(), |path| path.display().to_string())
+}
+
+/// The path of a source as it is on disk, for the lists of files meant to be read by other programs
+#[cfg(unix)]
+fn source_bytes(source: Option<&Path>, stdin_path: Option<&Path>) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    source.or(stdin_path).map_or_else(|| STDIN_LABEL.as_bytes().to_owned(), |path| path.as_os_str().as_bytes().to_owned())
+}
+
+#[cfg(not(unix))]
+fn source_bytes(source: Option<&Path>, stdin_path: Option<&Path>) -> Vec<u8> {
+    source_label(source, stdin_path).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mdfmt-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn failed_writes_keep_the_original() {
+        let dir = temp_dir("failed-write");
+        let path = dir.join("a.md");
+        fs::write(&path, "original\n").unwrap();
+        let err = write_atomically_with(&path, |file| {
+            file.write_all(b"half")?;
+            Err(io::Error::other("simulated failure"))
+        }).unwrap_err();
+
+        assert_eq!(err.to_string(), "simulated failure");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original\n");
+        // The temporary file was removed
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_kept() {
+        let dir = temp_dir("symlink-write");
+        fs::create_dir(dir.join("docs")).unwrap();
+        fs::write(dir.join("docs/a.md"), "original\n").unwrap();
+        std::os::unix::fs::symlink("docs/a.md", dir.join("link.md")).unwrap();
+        write_atomically(&dir.join("link.md"), b"formatted\n").unwrap();
+
+        assert!(fs::symlink_metadata(dir.join("link.md")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(dir.join("docs/a.md")).unwrap(), "formatted\n");
+        assert_eq!(fs::read_dir(dir.join("docs")).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_writes_use_their_own_temporary_file() {
+        let dir = temp_dir("concurrent-write");
+        let path = dir.join("a.md");
+        fs::write(&path, "original\n").unwrap();
+        // The inner write runs while the temporary file of the outer one exists, which it would collide with if they had the same name
+        write_atomically_with(&path, |_| write_atomically(&path, b"inner\n")).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,697 @@
+use std::str::FromStr;
+use crate::text::is_thematic_break;
+
+/// Configures how `format_content` formats a document.
+///
+/// The defaults match the behavior of the `mdfmt` binary when no flags are given.
+#[derive(Clone, Debug)]
+pub struct FormatOptions {
+    pub(crate) strict: bool,
+    pub(crate) format_tables: bool,
+    pub(crate) lint_tables: bool,
+    pub(crate) warn_table_width: Option<usize>,
+    pub(crate) line_ending: LineEnding,
+    pub(crate) final_newline: FinalNewline,
+    pub(crate) tab_width: usize,
+    pub(crate) indent_style: IndentStyle,
+    pub(crate) allow_loose_pipes: bool,
+    pub(crate) strict_cell_count: bool,
+    pub(crate) compact: bool,
+    pub(crate) max_col_width: Option<usize>,
+    pub(crate) min_col_width: usize,
+    pub(crate) trim_trailing_whitespace: bool,
+    pub(crate) max_blank_lines: Option<usize>,
+    pub(crate) bullet_char: Option<BulletChar>,
+    pub(crate) ordered_list_style: OrderedListStyle,
+    pub(crate) atx_closed_headings: bool,
+    pub(crate) fix_heading_space: bool,
+    pub(crate) heading_style: HeadingStyle,
+    pub(crate) auto_number_align: bool,
+    pub(crate) align_decimal: bool,
+    pub(crate) normalize_code_fence: Option<usize>,
+    pub(crate) fit_delimiter_width: bool,
+    pub(crate) emphasis_char: Option<EmphasisChar>,
+    pub(crate) strong_char: Option<EmphasisChar>,
+    pub(crate) normalize_link_definitions: bool,
+    pub(crate) lowercase_link_labels: bool,
+    pub(crate) renumber_footnotes: bool,
+    pub(crate) print_width: Option<usize>,
+    pub(crate) blockquote_style: BlockquoteStyle,
+    pub(crate) keep_bom: bool,
+    pub(crate) justify_cells: bool,
+    pub(crate) ascii_safe: bool,
+    pub(crate) unicode_dashes: bool,
+    pub(crate) normalize_table_captions: bool,
+    pub(crate) pipe_style: PipeStyle,
+    pub(crate) outer_pipes: bool,
+    pub(crate) sort_by: Option<SortBy>,
+    pub(crate) thematic_break: Option<ThematicBreak>,
+    pub(crate) collapse_cell_spaces: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            strict: false,
+            format_tables: true,
+            lint_tables: false,
+            warn_table_width: None,
+            line_ending: LineEnding::Auto,
+            final_newline: FinalNewline::Preserve,
+            tab_width: 4,
+            indent_style: IndentStyle::Preserve,
+            allow_loose_pipes: false,
+            strict_cell_count: false,
+            compact: false,
+            max_col_width: None,
+            min_col_width: 1,
+            trim_trailing_whitespace: true,
+            max_blank_lines: Some(1),
+            bullet_char: Some(BulletChar::Dash),
+            ordered_list_style: OrderedListStyle::Preserve,
+            atx_closed_headings: true,
+            fix_heading_space: false,
+            heading_style: HeadingStyle::Preserve,
+            auto_number_align: false,
+            align_decimal: false,
+            normalize_code_fence: None,
+            fit_delimiter_width: true,
+            emphasis_char: None,
+            strong_char: None,
+            normalize_link_definitions: false,
+            lowercase_link_labels: false,
+            renumber_footnotes: false,
+            print_width: None,
+            blockquote_style: BlockquoteStyle::Preserve,
+            keep_bom: false,
+            justify_cells: false,
+            ascii_safe: false,
+            unicode_dashes: false,
+            normalize_table_captions: false,
+            pipe_style: PipeStyle::Padded,
+            outer_pipes: true,
+            sort_by: None,
+            thematic_break: None,
+            collapse_cell_spaces: false,
+        }
+    }
+}
+
+impl FormatOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Warn if the input contains broken tables (instead of ignoring them), or tables with the same header in several columns
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Format the tables, or copy them verbatim if false while still formatting the rest of the document
+    pub fn format_tables(mut self, format_tables: bool) -> Self {
+        self.format_tables = format_tables;
+        self
+    }
+
+    /// Collapse the runs of spaces inside table cells to a single space, except in code spans where they are meaningful
+    pub fn collapse_cell_spaces(mut self, collapse_cell_spaces: bool) -> Self {
+        self.collapse_cell_spaces = collapse_cell_spaces;
+        self
+    }
+
+    /// Also report each table whose source was reformatted, with how many of its columns changed, as a lint.
+    ///
+    /// This shows where the churn of formatting a document for the first time comes from.
+    /// The tables with a header but no body rows are reported too, since they are often a mistake.
+    pub fn lint_tables(mut self, lint_tables: bool) -> Self {
+        self.lint_tables = lint_tables;
+        self
+    }
+
+    /// With `lint_tables`, also report the tables that are wider than this many columns once formatted,
+    /// since they wrap badly in narrow viewers. This doesn't change the output, see `max_col_width` for that.
+    pub fn warn_table_width(mut self, warn_table_width: Option<usize>) -> Self {
+        self.warn_table_width = warn_table_width;
+        self
+    }
+
+    /// Which line endings to use in the output
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Whether the output ends with a newline
+    pub fn final_newline(mut self, final_newline: FinalNewline) -> Self {
+        self.final_newline = final_newline;
+        self
+    }
+
+    /// End the output with a newline even if the input didn't have one, like `FinalNewline::Ensure`,
+    /// or keep the final newline of the input as it is if false
+    pub fn ensure_final_newline(mut self, ensure_final_newline: bool) -> Self {
+        self.final_newline = if ensure_final_newline { FinalNewline::Ensure } else { FinalNewline::Preserve };
+        self
+    }
+
+    /// How many columns a tab stop spans, tabs inside table cells are expanded to spaces
+    pub fn tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width.max(1);
+        self
+    }
+
+    /// Rewrite the indentation of text lines with only spaces or with as many tabs of `tab_width` as possible,
+    /// keeping how deep it is.
+    ///
+    /// Only the whitespace at the start of lines is changed, and never in code blocks, HTML blocks or tables.
+    pub fn indent_style(mut self, indent_style: IndentStyle) -> Self {
+        self.indent_style = indent_style;
+        self
+    }
+
+    /// Also recognize tables whose rows don't start and end with a pipe (e.g. `a | b`), as long as they have a delimiter row
+    pub fn allow_loose_pipes(mut self, allow_loose_pipes: bool) -> Self {
+        self.allow_loose_pipes = allow_loose_pipes;
+        self
+    }
+
+    /// Leave the tables with rows that don't have as many cells as the header as they are, as broken tables.
+    ///
+    /// Otherwise they are formatted like GFM renders them: the missing cells of short rows are left empty,
+    /// and the cells of long rows after the header's last column are dropped.
+    pub fn strict_cell_count(mut self, strict_cell_count: bool) -> Self {
+        self.strict_cell_count = strict_cell_count;
+        self
+    }
+
+    /// Write tables without padding or aligning their cells (e.g. `|a|b|`)
+    pub fn compact(mut self, compact: bool) -> Self {
+        self.compact = compact;
+        self
+    }
+
+    /// Wrap the body cells wider than this on continuation rows, breaking between words when possible.
+    ///
+    /// Each continuation row is a row of its own once rendered, and header cells are never wrapped.
+    /// Cells with `<br>` line breaks are also wrapped after each of them, so their lines line up.
+    pub fn max_col_width(mut self, max_col_width: Option<usize>) -> Self {
+        self.max_col_width = max_col_width.map(|width| width.max(1));
+        self
+    }
+
+    /// Pad every table column to at least this width, even if all its cells are shorter
+    pub fn min_col_width(mut self, min_col_width: usize) -> Self {
+        self.min_col_width = min_col_width.max(1);
+        self
+    }
+
+    /// Remove trailing whitespace outside of tables and code blocks, except for the two spaces of a hard line break
+    pub fn trim_trailing_whitespace(mut self, trim_trailing_whitespace: bool) -> Self {
+        self.trim_trailing_whitespace = trim_trailing_whitespace;
+        self
+    }
+
+    /// Collapse runs of blank lines outside of code blocks to at most this many lines, or keep them all if None.
+    ///
+    /// With 0, the blocks that were separated by blank lines can become one, like two tables.
+    pub fn max_blank_lines(mut self, max_blank_lines: Option<usize>) -> Self {
+        self.max_blank_lines = max_blank_lines;
+        self
+    }
+
+    /// Rewrite the markers of unordered list items to this one, or leave them as they are if None
+    pub fn bullet_char(mut self, bullet_char: Option<BulletChar>) -> Self {
+        self.bullet_char = bullet_char;
+        self
+    }
+
+    /// How to number the items of ordered lists
+    pub fn ordered_list_style(mut self, ordered_list_style: OrderedListStyle) -> Self {
+        self.ordered_list_style = ordered_list_style;
+        self
+    }
+
+    /// Keep the optional closing `#` of headings like `## Title ##`, instead of removing them
+    pub fn atx_closed_headings(mut self, atx_closed_headings: bool) -> Self {
+        self.atx_closed_headings = atx_closed_headings;
+        self
+    }
+
+    /// Add the space missing after the `#` of lines like `#Title`, which are otherwise paragraphs (like `#hashtag`).
+    ///
+    /// Lines that continue a paragraph and lines like `#123` are left alone, the space would change what they mean.
+    pub fn fix_heading_space(mut self, fix_heading_space: bool) -> Self {
+        self.fix_heading_space = fix_heading_space;
+        self
+    }
+
+    /// How to write level 1 and 2 headings
+    pub fn heading_style(mut self, heading_style: HeadingStyle) -> Self {
+        self.heading_style = heading_style;
+        self
+    }
+
+    /// Right-align the table columns that only contain numbers, unless their delimiter row already sets an alignment
+    pub fn auto_number_align(mut self, auto_number_align: bool) -> Self {
+        self.auto_number_align = auto_number_align;
+        self
+    }
+
+    /// Line up the decimal points of the table columns that only contain numbers, like `1.5` over `10.25`.
+    ///
+    /// The numbers are padded so that they end up as wide as each other, then the column is padded as usual,
+    /// so the header and the empty cells keep the alignment of the column.
+    pub fn align_decimal(mut self, align_decimal: bool) -> Self {
+        self.align_decimal = align_decimal;
+        self
+    }
+
+    /// Rewrite the fences of code blocks to be this long (at least 3), or leave them as they are if None.
+    ///
+    /// Fences are made longer when needed so that they aren't closed by a shorter fence inside the block,
+    /// the info string is kept on the opening fence and the content is left untouched.
+    pub fn normalize_code_fence(mut self, normalize_code_fence: Option<usize>) -> Self {
+        self.normalize_code_fence = normalize_code_fence.map(|len| len.max(3));
+        self
+    }
+
+    /// Make the delimiter row of tables as wide as their columns, instead of the shortest valid delimiters (`---` or `:-:`).
+    ///
+    /// Short delimiters don't change when a cell gets wider, which keeps diffs smaller.
+    pub fn fit_delimiter_width(mut self, fit_delimiter_width: bool) -> Self {
+        self.fit_delimiter_width = fit_delimiter_width;
+        self
+    }
+
+    /// Rewrite the markers of emphasis (`*a*` or `_a_`) to this one, or leave them as they are if None
+    pub fn emphasis_char(mut self, emphasis_char: Option<EmphasisChar>) -> Self {
+        self.emphasis_char = emphasis_char;
+        self
+    }
+
+    /// Rewrite the markers of strong emphasis (`**a**` or `__a__`) to this one, or leave them as they are if None
+    pub fn strong_char(mut self, strong_char: Option<EmphasisChar>) -> Self {
+        self.strong_char = strong_char;
+        self
+    }
+
+    /// Rewrite link reference definitions like `[label]:   url  'title'` with one space after the colon,
+    /// the spaces of the label collapsed and the title between double quotes (unless it contains some)
+    pub fn normalize_link_definitions(mut self, normalize_link_definitions: bool) -> Self {
+        self.normalize_link_definitions = normalize_link_definitions;
+        self
+    }
+
+    /// Also lowercase the labels of the link reference definitions that are normalized, which doesn't change the links
+    /// they are used by since labels are matched without case
+    pub fn lowercase_link_labels(mut self, lowercase_link_labels: bool) -> Self {
+        self.lowercase_link_labels = lowercase_link_labels;
+        self
+    }
+
+    /// Renumber the footnotes with numeric labels like `[^3]` from 1, in the order they are first referenced.
+    ///
+    /// `Formatter` can't look ahead, so it numbers them in the order they first appear, definitions included.
+    pub fn renumber_footnotes(mut self, renumber_footnotes: bool) -> Self {
+        self.renumber_footnotes = renumber_footnotes;
+        self
+    }
+
+    /// Wrap the lines of plain paragraphs at this many columns, or leave them as they are if None.
+    ///
+    /// This joins the lines of each paragraph before wrapping them, so it loses any meaningful line breaks
+    /// other than hard line breaks. List items, block quotes, headings, tables and code are never wrapped.
+    pub fn print_width(mut self, print_width: Option<usize>) -> Self {
+        self.print_width = print_width.map(|width| width.max(1));
+        self
+    }
+
+    /// How to write the `>` markers of block quotes
+    pub fn blockquote_style(mut self, blockquote_style: BlockquoteStyle) -> Self {
+        self.blockquote_style = blockquote_style;
+        self
+    }
+
+    /// Keep the UTF-8 byte order mark at the start of the input, instead of removing it
+    pub fn keep_bom(mut self, keep_bom: bool) -> Self {
+        self.keep_bom = keep_bom;
+        self
+    }
+
+    /// Place the content of table cells according to the alignment of their column,
+    /// instead of always starting it on the left (right-aligned cells are padded on the left, centered ones on both sides)
+    pub fn justify_cells(mut self, justify_cells: bool) -> Self {
+        self.justify_cells = justify_cells;
+        self
+    }
+
+    /// Write the delimiter rows of tables with only `-` and `|`, without the colons that set the alignment of their columns,
+    /// for renderers that don't understand them. The columns are then padded as if they had no alignment.
+    ///
+    /// Delimiter rows are always written anew in ASCII, so nothing else of the source delimiter row ends up in the output.
+    pub fn ascii_safe(mut self, ascii_safe: bool) -> Self {
+        self.ascii_safe = ascii_safe;
+        self
+    }
+
+    /// Accept the dashes pasted from word processors (like the en dash `–`, the em dash `—` or the minus sign `−`)
+    /// in delimiter rows, which are then written with ASCII `-` like any other.
+    ///
+    /// Otherwise these rows aren't delimiter rows, so the table before them is left as regular text.
+    pub fn unicode_dashes(mut self, unicode_dashes: bool) -> Self {
+        self.unicode_dashes = unicode_dashes;
+        self
+    }
+
+    /// Write the captions of tables (like `Table:   Sales  `) with a single space after `Table:` and none at the end.
+    ///
+    /// Captions are always kept where they are next to their table, and never wrapped with the text around them.
+    pub fn normalize_table_captions(mut self, normalize_table_captions: bool) -> Self {
+        self.normalize_table_captions = normalize_table_captions;
+        self
+    }
+
+    /// Where to write a space between the pipes of tables and the content of their cells
+    pub fn pipe_style(mut self, pipe_style: PipeStyle) -> Self {
+        self.pipe_style = pipe_style;
+        self
+    }
+
+    /// Write the pipes at the start and end of table rows (the default), or leave them out like `a | b`.
+    ///
+    /// The rows without them are still read as tables, as if `allow_loose_pipes` was set. A table keeps its outer pipes
+    /// when it wouldn't read back the same without them: when it has a single column, when a row starts or ends
+    /// with an empty cell, or when its first cell would start another block (like `# a` or `- a`).
+    pub fn outer_pipes(mut self, outer_pipes: bool) -> Self {
+        self.outer_pipes = outer_pipes;
+        self
+    }
+
+    /// Sort the body rows of every table by one of its columns, or leave them in their order if None.
+    ///
+    /// The header and delimiter rows stay first, rows with equal cells keep their order,
+    /// and the tables with fewer columns or with cells wrapped to a maximum width are left as they are.
+    pub fn sort_by(mut self, sort_by: Option<SortBy>) -> Self {
+        self.sort_by = sort_by;
+        self
+    }
+
+    /// Rewrite every horizontal rule (`---`, `***`, `_ _ _`...) to this one, or leave them as they are if None.
+    ///
+    /// A `---` line right after a paragraph is the underline of a setext heading, and is never rewritten.
+    pub fn thematic_break(mut self, thematic_break: Option<ThematicBreak>) -> Self {
+        self.thematic_break = thematic_break;
+        self
+    }
+}
+
+/// The line ending style of the formatted output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    /// Use the line ending that is most common in the input
+    #[default]
+    Auto,
+    Lf,
+    Crlf,
+}
+
+/// Whether the formatted output ends with a newline
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FinalNewline {
+    /// End with a newline only if the input did
+    Preserve,
+    /// Always end with a newline, unless the document is empty
+    Ensure,
+    /// Never end with a newline, the blank lines at the end are removed too
+    Strip,
+}
+
+/// The marker of unordered list items
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BulletChar {
+    /// `- item`
+    Dash,
+    /// `* item`
+    Asterisk,
+    /// `+ item`
+    Plus,
+}
+
+impl BulletChar {
+    pub(crate) fn as_char(self) -> char {
+        match self {
+            BulletChar::Dash => '-',
+            BulletChar::Asterisk => '*',
+            BulletChar::Plus => '+',
+        }
+    }
+}
+
+/// The marker of emphasis and strong emphasis, which is doubled for strong emphasis
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmphasisChar {
+    /// `*a*` and `**a**`
+    Asterisk,
+    /// `_a_` and `__a__`
+    Underscore,
+}
+
+impl EmphasisChar {
+    pub(crate) fn as_char(self) -> char {
+        match self {
+            EmphasisChar::Asterisk => '*',
+            EmphasisChar::Underscore => '_',
+        }
+    }
+}
+
+/// How to number the items of ordered lists, nested lists are numbered on their own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrderedListStyle {
+    /// Keep the numbers as they are
+    Preserve,
+    /// Number the items sequentially (`1.`, `2.`, `3.`) from the number of the first item
+    Sequential,
+    /// Give every item the number of the first item (`1.`, `1.`, `1.`)
+    Lazy,
+}
+
+/// How to write the indentation of text lines, like the nested items of lists
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndentStyle {
+    /// Keep the indentation as it is
+    Preserve,
+    /// Indent with spaces, tabs are replaced by the spaces up to the next tab stop
+    Spaces,
+    /// Indent with tabs, followed by spaces for the columns that don't make a whole tab
+    Tabs,
+}
+
+/// How to write level 1 and 2 headings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeadingStyle {
+    /// Keep headings as they are
+    Preserve,
+    /// Prefix headings with `#` (`# Title`)
+    Atx,
+    /// Underline headings with `===` or `---`, the underline is made as wide as the heading
+    Setext,
+}
+
+/// How to write the `>` markers of block quotes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockquoteStyle {
+    /// Keep the markers as they are
+    Preserve,
+    /// Follow each marker with a space (`> > text`), and add the markers to lazy continuation lines
+    Spaced,
+    /// Write nested markers together (`>> text`), and add the markers to lazy continuation lines
+    Compact,
+}
+
+/// Where tables have a space between their pipes and the content of their cells
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PipeStyle {
+    /// Next to every pipe (`| a | b |`)
+    Padded,
+    /// Only next to the pipes between cells (`|a | b|`)
+    TightOuter,
+    /// Only next to the pipes at the start and end of rows (`| a|b |`)
+    TightInner,
+}
+
+/// How to sort the body rows of tables, parsed from `COLUMN[:asc|desc][:auto|lexical|numeric]` like `2:desc`.
+///
+/// Columns are counted from 0, and rows are sorted in ascending order unless given otherwise. By default the cells are
+/// compared numerically if they are all numbers (like for `auto_number_align`) and lexically otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortBy {
+    pub(crate) column: usize,
+    pub(crate) descending: bool,
+    /// None to pick the comparator from the cells of each table
+    pub(crate) comparator: Option<SortComparator>,
+}
+
+/// How the cells of the column that rows are sorted by are compared
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortComparator {
+    /// Alphabetically, ignoring case
+    Lexical,
+    /// By their value if they are numbers like `-1,234.5` (and `10` comes after `9`), the other cells come after them
+    Numeric,
+}
+
+/// How to write horizontal rules, like `---` or `* * *`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThematicBreak(pub(crate) String);
+
+impl FromStr for LineEnding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(LineEnding::Auto),
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            _ => Err(format!("'{}' is not a line ending (expected auto, lf or crlf)", s)),
+        }
+    }
+}
+
+impl FromStr for FinalNewline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(FinalNewline::Preserve),
+            "ensure" => Ok(FinalNewline::Ensure),
+            "strip" => Ok(FinalNewline::Strip),
+            _ => Err(format!("'{}' is not a final newline style (expected preserve, ensure or strip)", s)),
+        }
+    }
+}
+
+impl FromStr for BulletChar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "-" => Ok(BulletChar::Dash),
+            "*" => Ok(BulletChar::Asterisk),
+            "+" => Ok(BulletChar::Plus),
+            _ => Err(format!("'{}' is not a bullet (expected -, * or +)", s)),
+        }
+    }
+}
+
+impl FromStr for EmphasisChar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "*" => Ok(EmphasisChar::Asterisk),
+            "_" => Ok(EmphasisChar::Underscore),
+            _ => Err(format!("'{}' is not an emphasis marker (expected * or _)", s)),
+        }
+    }
+}
+
+impl FromStr for OrderedListStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(OrderedListStyle::Preserve),
+            "sequential" => Ok(OrderedListStyle::Sequential),
+            "lazy" => Ok(OrderedListStyle::Lazy),
+            _ => Err(format!("'{}' is not an ordered list style (expected preserve, sequential or lazy)", s)),
+        }
+    }
+}
+
+impl FromStr for IndentStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(IndentStyle::Preserve),
+            "spaces" => Ok(IndentStyle::Spaces),
+            "tabs" => Ok(IndentStyle::Tabs),
+            _ => Err(format!("'{}' is not an indent style (expected preserve, spaces or tabs)", s)),
+        }
+    }
+}
+
+impl FromStr for HeadingStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(HeadingStyle::Preserve),
+            "atx" => Ok(HeadingStyle::Atx),
+            "setext" => Ok(HeadingStyle::Setext),
+            _ => Err(format!("'{}' is not a heading style (expected preserve, atx or setext)", s)),
+        }
+    }
+}
+
+impl FromStr for BlockquoteStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(BlockquoteStyle::Preserve),
+            "spaced" => Ok(BlockquoteStyle::Spaced),
+            "compact" => Ok(BlockquoteStyle::Compact),
+            _ => Err(format!("'{}' is not a block quote style (expected preserve, spaced or compact)", s)),
+        }
+    }
+}
+
+impl FromStr for PipeStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "padded" => Ok(PipeStyle::Padded),
+            "tight-outer" => Ok(PipeStyle::TightOuter),
+            "tight-inner" => Ok(PipeStyle::TightInner),
+            _ => Err(format!("'{}' is not a pipe style (expected padded, tight-outer or tight-inner)", s)),
+        }
+    }
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let column = parts.next().unwrap_or_default().parse()
+            .map_err(|_| format!("'{}' doesn't start with a column (expected COLUMN[:asc|desc][:auto|lexical|numeric])", s))?;
+        let mut sort_by = SortBy { column, descending: false, comparator: None };
+        for part in parts {
+            match part {
+                "asc" => sort_by.descending = false,
+                "desc" => sort_by.descending = true,
+                "auto" => sort_by.comparator = None,
+                "lexical" => sort_by.comparator = Some(SortComparator::Lexical),
+                "numeric" => sort_by.comparator = Some(SortComparator::Numeric),
+                _ => return Err(format!("'{}' is not a sort order (expected asc, desc, auto, lexical or numeric)", part)),
+            }
+        }
+        Ok(sort_by)
+    }
+}
+
+impl FromStr for ThematicBreak {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim() == s && is_thematic_break(s) {
+            Ok(ThematicBreak(s.to_owned()))
+        } else {
+            Err(format!("'{}' is not a horizontal rule (expected at least 3 -, * or _, which can be separated by spaces)", s))
+        }
+    }
+}
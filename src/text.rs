@@ -0,0 +1,590 @@
+use std::borrow::Cow;
+use crate::footnote::footnote_definition_len;
+use crate::format::split_words;
+use crate::inline::normalize_emphasis;
+use crate::width::display_width;
+use crate::options::{BlockquoteStyle, EmphasisChar, FormatOptions, HeadingStyle, IndentStyle, OrderedListStyle};
+
+/// What normalizing the previous lines of the same text region taught us about the next ones
+#[derive(Default)]
+pub(crate) struct TextState {
+    /// The ordered lists that are still open, innermost last
+    ordered_lists: Vec<OrderedList>,
+    previous_blank: bool,
+    /// The markers of the previous block quote line, if the next line can be a lazy continuation of it
+    lazy_quote: Option<String>,
+    /// Whether the previous line can be continued by a line of text, like a paragraph or a table row
+    continued_by_text: bool,
+    /// The column where the text of that paragraph starts, which its lines indented by 4 more columns only continue
+    paragraph_column: usize,
+}
+
+impl TextState {
+    /// Forgets the paragraph the previous lines were in, since something other than text (like a table) came after it
+    pub(crate) fn interrupt(&mut self) {
+        self.lazy_quote = None;
+        // A table row or an HTML block is continued by text too
+        self.continued_by_text = true;
+        self.paragraph_column = 0;
+    }
+}
+
+struct OrderedList {
+    indent: usize,
+    delimiter: char,
+    next_number: u64,
+}
+
+/// Applies the enabled normalizations to a line of regular text (outside of tables and code blocks)
+pub(crate) fn normalize_text_line<'a>(line: &'a str, options: &FormatOptions, state: &mut TextState) -> Cow<'a, str> {
+    let mut line = Cow::Borrowed(line);
+    if options.trim_trailing_whitespace {
+        line = map_cow(line, trim_trailing_whitespace);
+    }
+    if options.blockquote_style != BlockquoteStyle::Preserve {
+        line = map_cow(line, |line| normalize_blockquote(line, options.blockquote_style, state));
+    }
+    // `#Title` right after text continues its paragraph, so the space is only added to the lines that start a block
+    let add_heading_space = options.fix_heading_space && !state.continued_by_text;
+    line = map_cow(line, |line| normalize_atx_heading(line, options.atx_closed_headings, add_heading_space));
+    let continues_text = std::mem::replace(&mut state.continued_by_text, !line.trim().is_empty() && !ends_text_block(&line));
+    if options.heading_style == HeadingStyle::Setext && !continues_text {
+        line = map_cow(line, atx_to_setext);
+    }
+    line = map_cow(line, |line| renumber_ordered_item(line, options.ordered_list_style, state));
+    let indent = indentation_width(&line, options.tab_width);
+    let content = line.trim_start_matches([' ', '\t']);
+    // A marker indented that far is part of the text of the paragraph, not a list item
+    let in_paragraph = continues_text && indent >= state.paragraph_column + 4;
+    if !in_paragraph {
+        if !continues_text || list_item_content_offset(content).is_some() {
+            state.paragraph_column = indent + list_item_content_offset(content).unwrap_or(0);
+        }
+        if let Some(bullet_char) = options.bullet_char {
+            line = map_cow(line, |line| normalize_bullet(line, bullet_char.as_char()));
+        }
+    }
+    if options.emphasis_char.is_some() || options.strong_char.is_some() {
+        let (emphasis_char, strong_char) = (options.emphasis_char.map(EmphasisChar::as_char), options.strong_char.map(EmphasisChar::as_char));
+        line = map_cow(line, |line| normalize_emphasis(line, emphasis_char, strong_char));
+    }
+    if options.normalize_table_captions {
+        line = map_cow(line, normalize_table_caption);
+    }
+    // Last, since the other normalizations find list items by counting the characters of the indentation
+    if options.indent_style != IndentStyle::Preserve {
+        line = map_cow(line, |line| normalize_indentation(line, options.indent_style, options.tab_width));
+    }
+    line
+}
+
+/// Applies `f` to a line that may already have been modified, without allocating if it wasn't and `f` doesn't need to
+fn map_cow<'a>(line: Cow<'a, str>, mut f: impl for<'b> FnMut(&'b str) -> Cow<'b, str>) -> Cow<'a, str> {
+    match line {
+        Cow::Borrowed(line) => f(line),
+        Cow::Owned(line) => Cow::Owned(f(&line).into_owned()),
+    }
+}
+
+/// How many columns the spaces and tabs at the start of a line take, with tabs every `tab_width`
+fn indentation_width(line: &str, tab_width: usize) -> usize {
+    line.chars().take_while(|&c| c == ' ' || c == '\t').fold(0, |width, c| match c {
+        '\t' => width + tab_width - width % tab_width,
+        _ => width + 1,
+    })
+}
+
+/// Rewrites the spaces and tabs at the start of a line in `indent_style`, as many columns deep with tabs every `tab_width`
+fn normalize_indentation(line: &str, indent_style: IndentStyle, tab_width: usize) -> Cow<'_, str> {
+    let content = line.trim_start_matches([' ', '\t']);
+    if content.is_empty() {
+        return Cow::Borrowed(line);
+    }
+    let indentation = &line[..line.len() - content.len()];
+    let width = indentation_width(indentation, tab_width);
+    let normalized = match indent_style {
+        IndentStyle::Tabs => format!("{}{}", "\t".repeat(width / tab_width), " ".repeat(width % tab_width)),
+        _ => " ".repeat(width),
+    };
+    if normalized == indentation {
+        Cow::Borrowed(line)
+    } else {
+        Cow::Owned(normalized + content)
+    }
+}
+
+/// Removes trailing whitespace, except for the two spaces of a hard line break
+fn trim_trailing_whitespace(line: &str) -> Cow<'_, str> {
+    let trimmed = line.trim_end();
+    if trimmed.len() == line.len() {
+        return Cow::Borrowed(line);
+    }
+    if trimmed.is_empty() {
+        return Cow::Borrowed("");
+    }
+
+    let trailing_spaces = line.len() - line.trim_end_matches(' ').len();
+    if trailing_spaces >= 2 {
+        Cow::Owned(format!("{}  ", trimmed))
+    } else {
+        Cow::Borrowed(trimmed)
+    }
+}
+
+/// Rewrites the marker of an unordered list item to `bullet_char`, keeping its indentation.
+/// Empty items are left alone: they can't interrupt a paragraph, so a lone `*` under text is part of it, but `-` would underline it.
+fn normalize_bullet(line: &str, bullet_char: char) -> Cow<'_, str> {
+    let content = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - content.len()];
+    match bullet_marker(content) {
+        Some(marker) if marker != bullet_char && !is_thematic_break(content) && !content[1..].trim().is_empty() => {
+            Cow::Owned(format!("{}{}{}", indent, bullet_char, &content[1..]))
+        },
+        _ => Cow::Borrowed(line),
+    }
+}
+
+/// Where the content of a list item starts after its indentation, given the line without it (like 2 for `- item`)
+pub(crate) fn list_item_content_offset(content: &str) -> Option<usize> {
+    if is_thematic_break(content) {
+        return None;
+    }
+    let marker_len = match bullet_marker(content) {
+        Some(_) => 1,
+        None => content.len() - parse_ordered_marker(content)?.2.len(),
+    };
+    let spaces = content[marker_len..].len() - content[marker_len..].trim_start_matches(' ').len();
+    // An empty item or one starting with indented code has its content one space after the marker
+    if spaces == 0 || spaces > 4 || content[marker_len..].trim().is_empty() {
+        Some(marker_len + 1)
+    } else {
+        Some(marker_len + spaces)
+    }
+}
+
+/// Returns the marker of an unordered list item, given the line without its indentation
+fn bullet_marker(content: &str) -> Option<char> {
+    let marker = content.chars().next().filter(|&c| c == '-' || c == '*' || c == '+')?;
+    if !matches!(content[1..].chars().next(), None | Some(' ' | '\t')) {
+        return None;
+    }
+    Some(marker)
+}
+
+/// Rewrites the markers of a block quote line in the given style, with one space before the content.
+/// Any further indentation of the content is kept, since it can make it code or part of a list item.
+fn normalize_blockquote<'a>(line: &'a str, style: BlockquoteStyle, state: &mut TextState) -> Cow<'a, str> {
+    let lazy_quote = state.lazy_quote.take();
+    let (indent, depth, content) = match parse_blockquote(line) {
+        Some(quote) => quote,
+        None => {
+            // A lazy continuation line is still part of the paragraph once the markers are added
+            return match lazy_quote {
+                Some(markers) if starts_plain_paragraph(line) => {
+                    let normalized = format!("{} {}", markers, line.trim_start());
+                    state.lazy_quote = Some(markers);
+                    Cow::Owned(normalized)
+                },
+                _ => Cow::Borrowed(line),
+            };
+        }
+    };
+    // The column of a tab depends on the markers before it, so changing them would change the content
+    if content.starts_with('\t') {
+        return Cow::Borrowed(line);
+    }
+
+    let markers = quote_markers(indent, depth, style);
+    let normalized = if content.is_empty() {
+        markers.clone()
+    } else {
+        format!("{} {}", markers, content)
+    };
+    if continues_lazily(content) {
+        state.lazy_quote = Some(markers);
+    }
+
+    if normalized == line {
+        Cow::Borrowed(line)
+    } else {
+        Cow::Owned(normalized)
+    }
+}
+
+/// The markers of a line nested in `depth` block quotes, after its indentation
+pub(crate) fn quote_markers(indent: &str, depth: usize, style: BlockquoteStyle) -> String {
+    match style {
+        BlockquoteStyle::Compact => format!("{}{}", indent, ">".repeat(depth)),
+        _ => format!("{}{}", indent, vec![">"; depth].join(" ")),
+    }
+}
+
+/// Splits a block quote line into its indentation, how many quotes it is nested in, and its content
+pub(crate) fn parse_blockquote(line: &str) -> Option<(&str, usize, &str)> {
+    let mut rest = line.trim_start_matches(' ');
+    let indent = &line[..line.len() - rest.len()];
+    if indent.len() > 3 || !rest.starts_with('>') {
+        return None;
+    }
+
+    let mut depth = 0;
+    loop {
+        depth += 1;
+        rest = &rest[1..];
+        let after_spaces = rest.trim_start_matches(' ');
+        if after_spaces.starts_with('>') && rest.len() - after_spaces.len() <= 3 {
+            rest = after_spaces;
+        } else {
+            return Some((indent, depth, rest.strip_prefix(' ').unwrap_or(rest)));
+        }
+    }
+}
+
+/// Whether the content of a block quote line is paragraph text, which the next line can continue without markers
+fn continues_lazily(content: &str) -> bool {
+    let indent = content.len() - content.trim_start_matches(' ').len();
+    indent < 4 && starts_plain_paragraph(content.trim_start_matches(' ')) && !content.trim_start().starts_with(['`', '~'])
+}
+
+struct AtxHeading<'a> {
+    indent: &'a str,
+    level: usize,
+    text: &'a str,
+    /// The optional `#` run at the end
+    closing: &'a str,
+}
+
+/// Whether a line is a `# Heading`
+pub(crate) fn is_atx_heading(line: &str) -> bool {
+    parse_atx_heading(line, false).is_some()
+}
+
+/// Parses a heading whose `#` run is followed by a space, a tab or the end of the line.
+/// With `missing_space`, a line like `#Title` is read as a heading written without its space too.
+fn parse_atx_heading(line: &str, missing_space: bool) -> Option<AtxHeading<'_>> {
+    let content = line.trim_start_matches(' ');
+    let indent = &line[..line.len() - content.len()];
+    let level = content.bytes().take_while(|&b| b == b'#').count();
+    if indent.len() > 3 || level == 0 || level > 6 {
+        return None;
+    }
+    let rest = &content[level..];
+    if !matches!(rest.chars().next(), None | Some(' ' | '\t')) {
+        // Even then `#123` is an issue reference, not a heading
+        if !missing_space || rest.starts_with(|c: char| c.is_ascii_digit()) {
+            return None;
+        }
+    }
+
+    let mut text = rest.trim();
+    let mut closing = "";
+    let without_closing = text.trim_end_matches('#');
+    if without_closing.len() < text.len() && (without_closing.is_empty() || without_closing.ends_with([' ', '\t'])) {
+        closing = &text[without_closing.len()..];
+        text = without_closing.trim_end();
+    }
+    Some(AtxHeading { indent, level, text, closing })
+}
+
+/// Puts exactly one space between the `#` of a heading and its text, and before its closing `#` if `keep_closing` is set.
+/// The space is added to lines like `#Title` only if `add_space` is set.
+fn normalize_atx_heading(line: &str, keep_closing: bool, add_space: bool) -> Cow<'_, str> {
+    let heading = match parse_atx_heading(line, add_space) {
+        Some(heading) => heading,
+        None => return Cow::Borrowed(line),
+    };
+
+    let mut normalized = format!("{}{}", heading.indent, "#".repeat(heading.level));
+    if !heading.text.is_empty() {
+        normalized.push(' ');
+        normalized.push_str(heading.text);
+    }
+    if keep_closing && !heading.closing.is_empty() {
+        normalized.push(' ');
+        normalized.push_str(heading.closing);
+    }
+
+    if normalized == line {
+        Cow::Borrowed(line)
+    } else {
+        Cow::Owned(normalized)
+    }
+}
+
+/// Whether a line can't be continued by the text on the next line, like a heading or a horizontal rule
+fn ends_text_block(line: &str) -> bool {
+    is_atx_heading(line) || is_thematic_break(line.trim_start_matches(' ')) || setext_underline_level(line).is_some()
+}
+
+/// Rewrites level 1 and 2 ATX headings as setext headings, the others have no setext form.
+/// The heading must not come right after text, which its setext form would continue.
+fn atx_to_setext(line: &str) -> Cow<'_, str> {
+    match parse_atx_heading(line, false) {
+        Some(heading) if heading.level <= 2 && !heading.text.is_empty() && is_setext_heading_text(heading.text) => {
+            Cow::Owned(format!("{}\n{}", heading.text, setext_underline(heading.level, heading.text)))
+        },
+        _ => Cow::Borrowed(line),
+    }
+}
+
+/// Whether `text` is read as the text of a setext heading when it's underlined,
+/// instead of starting another block (like `- item` or `> quote`) or a table
+fn is_setext_heading_text(text: &str) -> bool {
+    starts_plain_paragraph(text)
+        && !text.starts_with("```") && !text.starts_with("~~~")
+        && !text.contains('|')
+        && footnote_definition_len(text).is_none()
+        && normalize_link_definition(text, false).is_none()
+}
+
+/// Returns the heading level of a setext underline (`===` or `---`), given the line below a paragraph
+pub(crate) fn setext_underline_level(line: &str) -> Option<usize> {
+    let content = line.trim_start_matches(' ');
+    if line.len() - content.len() > 3 {
+        return None;
+    }
+    let underline = content.trim_end();
+    if !underline.is_empty() && underline.bytes().all(|b| b == b'=') {
+        Some(1)
+    } else if !underline.is_empty() && underline.bytes().all(|b| b == b'-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Returns an underline as wide as the heading, but still long enough to never look like a list item
+pub(crate) fn setext_underline(level: usize, text: &str) -> String {
+    let marker = if level == 1 { "=" } else { "-" };
+    marker.repeat(display_width(text).max(3))
+}
+
+/// Whether a line (without its block quote markers) is a caption like `Table: Sales by region`, which belongs to the table
+/// right above or below it. It's never a row of that table nor wrapped with the text around it.
+pub(crate) fn is_table_caption(line: &str) -> bool {
+    let content = line.trim_start_matches(' ');
+    line.len() - content.len() <= 3 && content.starts_with("Table:")
+}
+
+/// Writes a table caption with one space after `Table:`
+fn normalize_table_caption(line: &str) -> Cow<'_, str> {
+    if !is_table_caption(line) {
+        return Cow::Borrowed(line);
+    }
+    let (indent_and_label, caption) = line.split_at(line.find("Table:").unwrap() + "Table:".len());
+    let normalized = match caption.trim() {
+        "" => indent_and_label.to_owned(),
+        caption => format!("{} {}", indent_and_label, caption),
+    };
+    if normalized == line {
+        Cow::Borrowed(line)
+    } else {
+        Cow::Owned(normalized)
+    }
+}
+
+/// Whether a line starts a plain paragraph, which can become a setext heading
+pub(crate) fn starts_plain_paragraph(line: &str) -> bool {
+    let content = line.trim_start_matches(' ');
+    line.len() - content.len() <= 3
+        && !content.trim().is_empty()
+        && !content.starts_with(['>', '<', '#'])
+        && bullet_marker(content).is_none()
+        && parse_ordered_marker(content).is_none()
+        && !is_thematic_break(content)
+        && setext_underline_level(content).is_none()
+}
+
+/// Tracks which ordered list the line belongs to, and renumbers it if it's a list item
+fn renumber_ordered_item<'a>(line: &'a str, style: OrderedListStyle, state: &mut TextState) -> Cow<'a, str> {
+    if line.trim().is_empty() {
+        state.previous_blank = true;
+        return Cow::Borrowed(line);
+    }
+    let previous_blank = std::mem::replace(&mut state.previous_blank, false);
+
+    let content = line.trim_start_matches([' ', '\t']);
+    let indent = line.len() - content.len();
+    let (number, delimiter, after_marker) = match parse_ordered_marker(content) {
+        Some(marker) => marker,
+        None => {
+            // Lines that don't continue a list item end it, lazy continuation lines right after the item don't
+            if previous_blank || bullet_marker(content).is_some() {
+                state.ordered_lists.retain(|list| list.indent < indent);
+            }
+            return Cow::Borrowed(line);
+        }
+    };
+
+    state.ordered_lists.retain(|list| list.indent <= indent);
+    let increment = if style == OrderedListStyle::Sequential { 1 } else { 0 };
+    let new_number = match state.ordered_lists.last_mut() {
+        Some(list) if list.indent == indent && list.delimiter == delimiter => {
+            let new_number = list.next_number;
+            list.next_number += increment;
+            new_number
+        },
+        _ => {
+            // The first item's number is where the list starts, so it's always kept
+            state.ordered_lists.retain(|list| list.indent < indent);
+            state.ordered_lists.push(OrderedList {
+                indent,
+                delimiter,
+                next_number: number + increment,
+            });
+            number
+        },
+    };
+
+    if style == OrderedListStyle::Preserve || new_number == number {
+        return Cow::Borrowed(line);
+    }
+    Cow::Owned(format!("{}{}{}{}", &line[..indent], new_number, delimiter, after_marker))
+}
+
+/// Parses the `1.` or `1)` marker of an ordered list item into its number, delimiter, and the rest of the line
+fn parse_ordered_marker(content: &str) -> Option<(u64, char, &str)> {
+    let digits = content.bytes().take_while(u8::is_ascii_digit).count();
+    if digits == 0 || digits > 9 {
+        return None;
+    }
+    let delimiter = content[digits..].chars().next().filter(|&c| c == '.' || c == ')')?;
+    let after_marker = &content[digits+1..];
+    if !after_marker.is_empty() && !after_marker.starts_with([' ', '\t']) {
+        return None;
+    }
+    Some((content[..digits].parse().ok()?, delimiter, after_marker))
+}
+
+/// Whether a line is a `---`, `***` or `___` horizontal rule, which can also be written with spaces (`* * *`)
+pub(crate) fn is_thematic_break(line: &str) -> bool {
+    let line = line.trim();
+    let marker = match line.chars().next() {
+        Some(marker) if marker == '-' || marker == '*' || marker == '_' => marker,
+        _ => return false,
+    };
+    line.chars().all(|c| c == marker || c == ' ' || c == '\t') && line.chars().filter(|&c| c == marker).count() >= 3
+}
+
+/// Rewrites a link reference definition like `[label]:   <url>  'title'` as `[label]: <url> "title"`,
+/// or returns None if the line isn't a whole definition.
+///
+/// The spaces in the label are collapsed, and the label is lowercased if `lowercase_label` is set since labels are
+/// matched without case. Titles are written between double quotes, unless they contain some.
+pub(crate) fn normalize_link_definition(line: &str, lowercase_label: bool) -> Option<String> {
+    let content = line.trim_start_matches(' ');
+    let indent = &line[..line.len() - content.len()];
+    if indent.len() > 3 || !content.starts_with('[') {
+        return None;
+    }
+    let label_end = find_unescaped(&content[1..], &[']'])? + 1;
+    let label = &content[1..label_end];
+    // Footnotes look like definitions but aren't
+    if label.trim().is_empty() || label.starts_with('^') || find_unescaped(label, &['[']).is_some() {
+        return None;
+    }
+    let rest = content[label_end + 1..].strip_prefix(':')?.trim_start_matches([' ', '\t']);
+
+    let destination_len = if let Some(url) = rest.strip_prefix('<') {
+        2 + find_unescaped(url, &['<', '>']).filter(|&end| url[end..].starts_with('>'))?
+    } else {
+        rest.find([' ', '\t']).unwrap_or(rest.len())
+    };
+    if destination_len == 0 {
+        return None;
+    }
+    let destination = &rest[..destination_len];
+    let after_destination = &rest[destination_len..];
+    let title_source = after_destination.trim_matches([' ', '\t']);
+    if !title_source.is_empty() && !after_destination.starts_with([' ', '\t']) {
+        return None;
+    }
+
+    let mut label = label.split_whitespace().collect::<Vec<_>>().join(" ");
+    if lowercase_label {
+        label = label.to_lowercase();
+    }
+    let mut normalized = format!("{}[{}]: {}", indent, label, destination);
+    if !title_source.is_empty() {
+        let mut title_chars = title_source.chars();
+        let (open, close) = match (title_chars.next(), title_chars.next_back()) {
+            (Some('"'), Some('"')) => ('"', '"'),
+            (Some('\''), Some('\'')) => ('\'', '\''),
+            (Some('('), Some(')')) => ('(', ')'),
+            _ => return None,
+        };
+        let title = title_chars.as_str();
+        if find_unescaped(title, &[open, close]).is_some() {
+            return None;
+        }
+        if title.contains('"') {
+            normalized.push_str(&format!(" {}{}{}", open, title, close));
+        } else {
+            normalized.push_str(&format!(" \"{}\"", title));
+        }
+    }
+    Some(normalized)
+}
+
+/// The position of the first of `chars` in `text` that isn't escaped by a backslash
+fn find_unescaped(text: &str, chars: &[char]) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if !escaped && chars.contains(&c) {
+            return Some(i);
+        }
+        escaped = !escaped && c == '\\';
+    }
+    None
+}
+
+/// Joins the lines of a plain paragraph and wraps them again at `width` columns, keeping the indentation of its first line.
+///
+/// Hard line breaks (two trailing spaces or a backslash) are kept, and a word that would change the meaning of a line
+/// by starting it (like `-` or `#`) stays on the previous line.
+pub(crate) fn wrap_paragraph(paragraph: &str, width: usize) -> String {
+    let indent = &paragraph[..paragraph.len() - paragraph.trim_start_matches(' ').len()];
+    let lines = paragraph.lines().collect::<Vec<_>>();
+    let mut wrapped = String::new();
+    let mut words = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        words.extend(split_words(line.trim()));
+        let last = i + 1 == lines.len();
+        let trailing_backslashes = line.len() - line.trim_end_matches('\\').len();
+        if last || line.ends_with("  ") || trailing_backslashes % 2 == 1 {
+            write_wrapped_words(&mut wrapped, &words, indent, width);
+            if !last && line.ends_with("  ") {
+                wrapped.truncate(wrapped.len() - 1);
+                wrapped.push_str("  \n");
+            }
+            words.clear();
+        }
+    }
+    wrapped
+}
+
+fn write_wrapped_words(output: &mut String, words: &[&str], indent: &str, width: usize) {
+    let mut line = String::new();
+    for &word in words {
+        if line.is_empty() {
+            line.push_str(indent);
+            line.push_str(word);
+        } else if display_width(&line) + 1 + display_width(word) <= width || !can_start_line(word) {
+            line.push(' ');
+            line.push_str(word);
+        } else {
+            output.push_str(&line);
+            output.push('\n');
+            line = format!("{}{}", indent, word);
+        }
+    }
+    if !line.is_empty() {
+        output.push_str(&line);
+        output.push('\n');
+    }
+}
+
+/// Whether a paragraph line can start with this word without becoming something else, like a list item or a table
+fn can_start_line(word: &str) -> bool {
+    starts_plain_paragraph(word) && !word.starts_with(['|', '`', '~', '=', '-', '+', '*'])
+}
@@ -0,0 +1,249 @@
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs::read_dir;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use globset::{GlobBuilder, GlobMatcher};
+
+const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown"];
+
+/// Appends the Markdown files found in `dir` to `files`, descending into subdirectories if `recursive` is set.
+/// Entries that can't be read are appended to `failures` instead of aborting the walk.
+pub fn collect_markdown_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>, failures: &mut Vec<(PathBuf, Box<dyn Error>)>) {
+    let entries = match read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            failures.push((dir.to_owned(), err.into()));
+            return;
+        }
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        match entry {
+            Ok(entry) => paths.push(entry.path()),
+            Err(err) => failures.push((dir.to_owned(), err.into())),
+        }
+    }
+    // Sort so that the output order doesn't depend on the filesystem
+    paths.sort();
+
+    for path in paths {
+        if path.is_dir() {
+            if recursive {
+                collect_markdown_files(&path, recursive, files, failures);
+            }
+        } else if is_markdown_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+fn is_markdown_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| MARKDOWN_EXTENSIONS.iter().any(|md_ext| ext.eq_ignore_ascii_case(md_ext)))
+}
+
+/// Whether an argument is a glob pattern like `docs/**/*.md`, for shells that don't expand them
+pub fn is_glob(arg: &OsStr) -> bool {
+    arg.to_str().is_some_and(|arg| arg.contains(['*', '?', '[']))
+}
+
+/// Appends the files matching a glob `pattern` to `files`, sorted. Directories are never matched.
+///
+/// `*` matches any part of a name, `?` any character and `[abc]` or `[a-z]` (`[!a-z]` to negate) any of those characters,
+/// but none of them match the `.` starting the name of hidden files. A `**` component matches any number of directories.
+pub fn expand_glob(pattern: &str, files: &mut Vec<PathBuf>, failures: &mut Vec<(PathBuf, Box<dyn Error>)>) {
+    let components = pattern.split(['/', std::path::MAIN_SEPARATOR]).collect::<Vec<_>>();
+    let literal_len = components.iter().take_while(|component| !component.contains(['*', '?', '['])).count();
+    let base = match &components[..literal_len] {
+        [] => PathBuf::new(),
+        // An absolute pattern starts with an empty component
+        [""] => PathBuf::from("/"),
+        literal => PathBuf::from(literal.join("/")),
+    };
+    let mut components = components[literal_len..].to_vec();
+    // A trailing `**` matches every file under the directory
+    if components.last() == Some(&"**") {
+        components.push("*");
+    }
+    let components = match components.iter().map(|component| Component::new(component)).collect::<Result<Vec<_>, _>>() {
+        Ok(components) => components,
+        Err(err) => {
+            failures.push((PathBuf::from(pattern), err.into()));
+            return;
+        }
+    };
+
+    let mut matches = Vec::new();
+    let previous_failures = failures.len();
+    match_components(&base, &components, &mut matches, failures);
+    if matches.is_empty() && failures.len() == previous_failures {
+        failures.push((PathBuf::from(pattern), "No files match this pattern".into()));
+    }
+    matches.sort();
+    matches.dedup();
+    files.extend(matches);
+}
+
+/// One component of a glob pattern, between separators
+enum Component {
+    /// An empty component, from repeated or trailing separators
+    Empty,
+    /// `**`, any number of directories
+    AnyDirectories,
+    /// A pattern matching one file name, and whether it can match hidden names (only if it starts with a `.` itself)
+    Name(GlobMatcher, bool),
+}
+
+impl Component {
+    fn new(component: &str) -> Result<Self, globset::Error> {
+        Ok(match component {
+            "" => Component::Empty,
+            "**" => Component::AnyDirectories,
+            _ => Component::Name(GlobBuilder::new(component).literal_separator(true).build()?.compile_matcher(), component.starts_with('.')),
+        })
+    }
+
+    fn matches(&self, name: &OsStr) -> bool {
+        match self {
+            Component::Name(matcher, hidden) => (*hidden || !is_hidden(name)) && matcher.is_match(name),
+            _ => false,
+        }
+    }
+}
+
+fn is_hidden(name: &OsStr) -> bool {
+    name.as_encoded_bytes().first() == Some(&b'.')
+}
+
+fn match_components(dir: &Path, components: &[Component], matches: &mut Vec<PathBuf>, failures: &mut Vec<(PathBuf, Box<dyn Error>)>) {
+    let (component, rest) = match components {
+        [] => return,
+        // Repeated or trailing separators
+        [Component::Empty, rest @ ..] if !rest.is_empty() => return match_components(dir, rest, matches, failures),
+        [component, rest @ ..] => (component, rest),
+    };
+    let entries = match read_dir(if dir.as_os_str().is_empty() { Path::new(".") } else { dir }) {
+        Ok(entries) => entries,
+        Err(err) => {
+            failures.push((dir.to_owned(), err.into()));
+            return;
+        }
+    };
+
+    if let Component::AnyDirectories = component {
+        match_components(dir, rest, matches, failures);
+    }
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                failures.push((dir.to_owned(), err.into()));
+                continue;
+            }
+        };
+        let name = entry.file_name();
+        let path = dir.join(&name);
+        if let Component::AnyDirectories = component {
+            if path.is_dir() && !is_hidden(&name) {
+                match_components(&path, components, matches, failures);
+            }
+        } else if component.matches(&name) {
+            if rest.is_empty() {
+                if path.is_file() {
+                    matches.push(path);
+                }
+            } else if path.is_dir() {
+                match_components(&path, rest, matches, failures);
+            }
+        }
+    }
+}
+
+/// Reads the paths listed in `list` (or stdin if it is `-`), one per line or separated by NUL bytes if `separator` is 0.
+/// Empty entries are skipped, and the paths are otherwise taken exactly as written, spaces included.
+pub fn read_file_list(list: &Path, separator: u8) -> io::Result<Vec<PathBuf>> {
+    let mut content = Vec::new();
+    if list == Path::new("-") {
+        io::stdin().read_to_end(&mut content)?;
+    } else {
+        std::fs::File::open(list)?.read_to_end(&mut content)?;
+    }
+
+    let mut paths = Vec::new();
+    for entry in content.split(|&b| b == separator) {
+        let entry = match entry {
+            [entry @ .., b'\r'] if separator == b'\n' => entry,
+            entry => entry,
+        };
+        if !entry.is_empty() {
+            paths.push(path_from_bytes(entry));
+        }
+    }
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(dir: &Path, pattern: &str) -> Vec<PathBuf> {
+        let (mut files, mut failures) = (Vec::new(), Vec::new());
+        expand_glob(&format!("{}/{}", dir.display(), pattern), &mut files, &mut failures);
+        assert!(failures.is_empty(), "{:?}", failures.iter().map(|(path, err)| format!("{}: {}", path.display(), err)).collect::<Vec<_>>());
+        files.iter().map(|file| file.strip_prefix(dir).unwrap().to_owned()).collect()
+    }
+
+    #[test]
+    fn glob_patterns() {
+        let dir = std::env::temp_dir().join(format!("mdfmt-walk-{}", std::process::id()));
+        for file in ["a.md", "b.md", "c.txt", ".hidden.md", "sub/d.md", "sub/deep/e.md", ".git/f.md"] {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let paths = |paths: &[&str]| paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+        assert_eq!(expand(&dir, "*.md"), paths(&["a.md", "b.md"]));
+        assert_eq!(expand(&dir, "[!a].*"), paths(&["b.md", "c.txt"]));
+        assert_eq!(expand(&dir, "?.[a-m]*"), paths(&["a.md", "b.md"]));
+        assert_eq!(expand(&dir, ".*.md"), paths(&[".hidden.md"]));
+        assert_eq!(expand(&dir, "**/*.md"), paths(&["a.md", "b.md", "sub/d.md", "sub/deep/e.md"]));
+        assert_eq!(expand(&dir, "sub/**"), paths(&["sub/d.md", "sub/deep/e.md"]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_matched() {
+        let dir = std::env::temp_dir().join(format!("mdfmt-walk-utf8-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let name = path_from_bytes(b"caf\xe9.md");
+        std::fs::write(dir.join(&name), "").unwrap();
+
+        assert_eq!(expand(&dir, "*.md"), vec![name]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_patterns_are_failures() {
+        let (mut files, mut failures) = (Vec::new(), Vec::new());
+        expand_glob("[a.md", &mut files, &mut failures);
+        assert!(files.is_empty());
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].1.to_string().contains("unclosed character class"), "{}", failures[0].1);
+    }
+}
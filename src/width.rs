@@ -0,0 +1,54 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// How many columns `text` takes in a terminal or monospace editor.
+///
+/// The text is split in grapheme clusters, and the width of each one comes from unicode-width, which since 0.1.14 counts
+/// the sequences displayed as one character once: a flag made of two regional indicators, an emoji with a skin tone or
+/// joined to others by U+200D ZERO WIDTH JOINER, and a letter followed by combining accents. Measuring each character
+/// on its own would count these twice.
+///
+/// Right-to-left text is measured in its logical order, which is the order it's written in, and the bidi
+/// control characters (like U+200F RIGHT-TO-LEFT MARK) take no columns.
+///
+/// Zero-width joiners, zero-width spaces and variation selectors take no columns either, except that U+FE0F asks for the
+/// emoji presentation of the character before it, which then takes two columns (e.g. `❤️`). The characters are never
+/// removed from the cells, only left out of their width.
+pub(crate) fn display_width(text: &str) -> usize {
+    // ASCII has no clusters of several characters, and is most cells
+    if text.is_ascii() {
+        return text.width();
+    }
+    text.graphemes(true).map(UnicodeWidthStr::width).sum()
+}
+
+/// Splits `text` in its extended grapheme clusters, the characters that are displayed as one (like `e\u{301}` or `🇫🇷`),
+/// so that a width is only measured for whole clusters and wrapping a cell never breaks them apart
+pub(crate) fn clusters(text: &str) -> Vec<&str> {
+    text.graphemes(true).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clusters_count_once() {
+        assert_eq!(display_width("🇫🇷"), 2);
+        assert_eq!(display_width("👍🏽"), 2);
+        assert_eq!(display_width("👨\u{200D}👩\u{200D}👧"), 2);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("日本"), 4);
+        assert_eq!(display_width("\u{1100}\u{1161}\u{11A8}"), 2);
+    }
+
+    #[test]
+    fn clusters_are_not_split() {
+        assert_eq!(clusters("a🇫🇷e\u{301}👨\u{200D}👩"), vec!["a", "🇫🇷", "e\u{301}", "👨\u{200D}👩"]);
+        assert_eq!(clusters("🇫🇷🇩🇪"), vec!["🇫🇷", "🇩🇪"]);
+        // Hangul syllables written with conjoining jamo, Indic conjuncts and emoji with a skin tone
+        assert_eq!(clusters("\u{1100}\u{1161}\u{11A8}\u{1100}\u{1161}"), vec!["\u{1100}\u{1161}\u{11A8}", "\u{1100}\u{1161}"]);
+        assert_eq!(clusters("\u{915}\u{94D}\u{937}\u{93F}"), vec!["\u{915}\u{94D}\u{937}\u{93F}"]);
+        assert_eq!(clusters("👍🏽👍"), vec!["👍🏽", "👍"]);
+    }
+}
@@ -0,0 +1,171 @@
+//! The library API: the options, the entry points and what they return
+
+use mdfmt::{format_bytes, format_content, format_range, format_to_writer, BulletChar, Diagnostic, EmphasisChar, FinalNewline, FormatError, FormatOptions, Formatter, Severity};
+
+#[test]
+fn writer_matches_string_output() {
+    let inputs = [
+        "", "\n", "abc", "abc\n\t", "<div\n\t", "|a|\n|-|\n\t", "|a|b|\n|-|-|\n|1|2|",
+        "Title\n---\n\n|a|b|\n|-|-|\n|1|2|\n\n\n", "para\ngraph  \nwith a break\n\n```\n|a|\n```\n",
+        "> |a|\n> |-|\n\n- item\n", "|a|\r\n|-|\r\n",
+    ];
+    let options = [
+        FormatOptions::new(),
+        FormatOptions::new().print_width(Some(10)),
+        FormatOptions::new().final_newline(FinalNewline::Ensure),
+        FormatOptions::new().final_newline(FinalNewline::Strip),
+        FormatOptions::new().strict(true).normalize_code_fence(Some(3)),
+    ];
+    for options in &options {
+        for input in &inputs {
+            let formatted = format_content(input, options).unwrap();
+            let mut written = Vec::new();
+            let diagnostics = format_to_writer(input, options, &mut written).unwrap();
+            assert_eq!(String::from_utf8(written).unwrap(), formatted.content, "{:?}", input);
+            assert_eq!(diagnostics, formatted.diagnostics);
+        }
+    }
+}
+
+#[test]
+fn bullets_are_parsed_instead_of_checked() {
+    assert_eq!("*".parse::<BulletChar>(), Ok(BulletChar::Asterisk));
+    assert_eq!("x".parse::<BulletChar>(), Err("'x' is not a bullet (expected -, * or +)".to_owned()));
+    let options = FormatOptions::new().bullet_char(Some(BulletChar::Plus));
+    assert_eq!(format_content("- a\n* b\n", &options).unwrap().content, "+ a\n+ b\n");
+}
+
+#[test]
+fn emphasis_markers_are_parsed_instead_of_checked() {
+    assert_eq!("_".parse::<EmphasisChar>(), Ok(EmphasisChar::Underscore));
+    assert_eq!("~".parse::<EmphasisChar>(), Err("'~' is not an emphasis marker (expected * or _)".to_owned()));
+    let options = FormatOptions::new().emphasis_char(Some(EmphasisChar::Underscore)).strong_char(Some(EmphasisChar::Asterisk));
+    assert_eq!(format_content("*a* __b__\n", &options).unwrap().content, "_a_ **b**\n");
+}
+
+#[test]
+fn default_options_format_tables_leniently() {
+    let input = "Some text\n\n|a|b|\n|-|-|\n|1|2|3|\n";
+    let formatted = format_content(input, &FormatOptions::default()).unwrap();
+    assert_eq!(formatted.content, "Some text\n\n| a | b |\n|---|---|\n| 1 | 2 |\n");
+    assert!(formatted.diagnostics.is_empty());
+    assert_eq!(format_content(input, &FormatOptions::new()).unwrap().content, formatted.content);
+}
+
+#[test]
+fn wrong_cell_counts_are_diagnostics() {
+    let formatted = format_content("|a|b|\n|-|-|\n|1|2|3|\n", &FormatOptions::new().strict(true)).unwrap();
+    assert_eq!(formatted.content, "| a | b |\n|---|---|\n| 1 | 2 |\n");
+    assert_eq!(formatted.diagnostics, [Diagnostic {
+        line: 3,
+        column: 1,
+        severity: Severity::Warning,
+        message: "The row has 3 cells, the ones after the 2 of the header are dropped".to_owned(),
+    }]);
+}
+
+#[test]
+fn streaming_holds_back_only_the_current_block() {
+    let block = "Some text\nover two lines\n\n|a|b|\n|-|-|\n|1|2|\n\n";
+    let input = block.repeat(5_000);
+    let options = FormatOptions::new();
+    let mut formatter = Formatter::new(&options);
+    let (mut pushed, mut output) = (0, String::new());
+    let mut most_held_back = 0;
+    for line in input.split_inclusive('\n') {
+        formatter.push_line(line).unwrap();
+        pushed += line.len();
+        output.push_str(&formatter.take_output());
+        // The formatted tables are a bit wider than their source
+        most_held_back = most_held_back.max(pushed.saturating_sub(output.len()));
+    }
+    output.push_str(&formatter.finish().content);
+
+    assert!(most_held_back < 2 * block.len(), "{} bytes were held back", most_held_back);
+    assert_eq!(output, format_content(&input, &options).unwrap().content);
+}
+
+#[test]
+fn lint_reports_reformatted_tables() {
+    let options = FormatOptions::new().lint_tables(true);
+    let formatted = format_content("|a|b|\n|-|-|\n|1 | 2|\n|  x  |y|\n", &options).unwrap();
+    assert_eq!(formatted.diagnostics, [Diagnostic {
+        line: 1,
+        column: 1,
+        severity: Severity::Lint,
+        message: "The table was reformatted (reflowed 2 columns)".to_owned(),
+    }]);
+    assert!(format_content(&formatted.content, &options).unwrap().diagnostics.is_empty());
+}
+
+#[test]
+fn errors_can_be_matched() {
+    match format_bytes(b"ok\n|a|\xff|\n", &FormatOptions::default()) {
+        Err(FormatError::Encoding { line, column }) => assert_eq!((line, column), (2, 4)),
+        other => panic!("expected an encoding error, got {:?}", other.map(|formatted| formatted.content)),
+    }
+    assert_eq!(format_bytes(b"\xc3", &FormatOptions::default()).unwrap_err().to_string(), "1:1: The input is not valid UTF-8");
+}
+
+#[test]
+fn duplicate_headers() {
+    let input = "|a|b|a|\n|-|-|-|\n|1|2|3|\n";
+    let formatted = format_content(input, &FormatOptions::new().strict(true)).unwrap();
+    assert_eq!(formatted.diagnostics, [Diagnostic {
+        line: 1,
+        column: 1,
+        severity: Severity::Warning,
+        message: "The header 'a' is repeated in columns 1 and 3".to_owned(),
+    }]);
+    let lenient = format_content(input, &FormatOptions::default()).unwrap();
+    assert!(lenient.diagnostics.is_empty());
+    assert_eq!(lenient.content, "| a | b | a |\n|---|---|---|\n| 1 | 2 | 3 |\n");
+    assert_eq!(lenient.content, formatted.content);
+}
+
+#[test]
+fn range_formatting() {
+    let input = "*  text  \n\n|a|b|\n|-|-|\n\n|c|\n|-|\n|1|\n\n```\n|x|\n```\n";
+    let options = FormatOptions::default();
+    // Exactly the first table
+    assert_eq!(format_range(input, 3, 4, &options).unwrap().content, "*  text  \n\n| a | b |\n|---|---|\n\n|c|\n|-|\n|1|\n\n```\n|x|\n```\n");
+    // The second table is only partly selected, so it's left alone
+    assert_eq!(format_range(input, 3, 7, &options).unwrap().content, "*  text  \n\n| a | b |\n|---|---|\n\n|c|\n|-|\n|1|\n\n```\n|x|\n```\n");
+    assert_eq!(format_range(input, 7, 8, &options).unwrap().content, input);
+    assert_eq!(format_range(input, 1, 11, &options).unwrap_err(), FormatError::SplitCodeBlock { line: 10 });
+}
+
+#[test]
+fn wide_tables_are_reported_in_lint_mode() {
+    let input = "| aaaaaaaaaa | bbbbbbbbbb |\n|------------|------------|\n| 1          | 2          |\n";
+    let options = FormatOptions::new().lint_tables(true).warn_table_width(Some(20));
+    let formatted = format_content(input, &options).unwrap();
+    assert_eq!(formatted.content, input);
+    assert_eq!(formatted.diagnostics, [Diagnostic {
+        line: 1,
+        column: 1,
+        severity: Severity::Lint,
+        message: "The table is 27 columns wide, more than 20".to_owned(),
+    }]);
+    // Exactly as wide as the threshold is fine
+    assert!(format_content(input, &options.warn_table_width(Some(27))).unwrap().diagnostics.is_empty());
+}
+
+#[test]
+fn header_only_tables() {
+    let input = "|a|bb|\n|-|-|\n\ntext\n";
+    let formatted = format_content(input, &FormatOptions::default()).unwrap();
+    assert_eq!(formatted.content, "| a | bb |\n|---|----|\n\ntext\n");
+    assert!(formatted.diagnostics.is_empty());
+
+    let options = FormatOptions::new().lint_tables(true);
+    let linted = format_content(&formatted.content, &options).unwrap();
+    assert_eq!(linted.content, formatted.content);
+    assert_eq!(linted.diagnostics, [Diagnostic {
+        line: 1,
+        column: 1,
+        severity: Severity::Lint,
+        message: "The table has a header but no body rows".to_owned(),
+    }]);
+    assert_eq!(format_content(input, &options).unwrap().diagnostics.len(), 2);
+}
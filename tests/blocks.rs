@@ -0,0 +1,112 @@
+//! The blocks that aren't formatted like tables: code, HTML, block quotes and the mdfmt directives
+
+use mdfmt::{format_content, BlockquoteStyle, BulletChar, FormatOptions};
+
+fn format(content: &str, options: &FormatOptions) -> String {
+    format_content(content, options).unwrap().content
+}
+
+#[test]
+fn diagnostics_count_the_lines_of_code_blocks() {
+    let input = "Text\n\n```\n|x|\n|-|\n```\n\n|a|b|\n|-|-|\n|1|2|3|\n";
+    let formatted = format_content(input, &FormatOptions::new().strict(true)).unwrap();
+    assert_eq!(formatted.content, "Text\n\n```\n|x|\n|-|\n```\n\n| a | b |\n|---|---|\n| 1 | 2 |\n");
+    assert_eq!(formatted.diagnostics.iter().map(|diagnostic| diagnostic.line).collect::<Vec<_>>(), [10]);
+}
+
+#[test]
+fn tables_in_tilde_fences_are_verbatim() {
+    let input = "~~~\n|a|b|\n|-|-|\n~~~\n\n~~~~md\n```\n|a|\n|-|\n```\n~~~~\n";
+    assert_eq!(format(input, &FormatOptions::default()), input);
+    assert_eq!(format("~~~\n|a|\n|-|\n~~~\n|b|\n|-|\n", &FormatOptions::default()), "~~~\n|a|\n|-|\n~~~\n| b |\n|---|\n");
+}
+
+#[test]
+fn longer_fences_contain_shorter_ones() {
+    let input = "````md\n```\n|a|b|\n|-|-|\n```\n````\n|a|\n|-|\n";
+    assert_eq!(format(input, &FormatOptions::default()), "````md\n```\n|a|b|\n|-|-|\n```\n````\n| a |\n|---|\n");
+}
+
+#[test]
+fn indented_code_is_verbatim() {
+    let options = FormatOptions::default();
+    assert_eq!(format("Text\n\n    |a|b|\n    |-|-|\n\n|c|\n|-|\n", &options), "Text\n\n    |a|b|\n    |-|-|\n\n| c |\n|---|\n");
+    assert_eq!(format("\t|a|b|\n\t|-|-|\n", &options), "\t|a|b|\n\t|-|-|\n");
+    // Code in a list item is indented past the content of the item
+    assert_eq!(format("- item\n\n      |a|b|\n      |-|-|\n", &options), "- item\n\n      |a|b|\n      |-|-|\n");
+}
+
+#[test]
+fn info_strings_are_kept() {
+    let input = "```rust title=\"x\"\n|a|\n```\n";
+    assert_eq!(format(input, &FormatOptions::default()), input);
+    let options = FormatOptions::new().normalize_code_fence(Some(4));
+    assert_eq!(format("``` rust\nfn main() {}\n```\n", &options), "````rust\nfn main() {}\n````\n");
+    assert_eq!(format("~~~  rust title=\"a b\"\nfn main() {}\n~~~\n", &options), "~~~~rust title=\"a b\"\nfn main() {}\n~~~~\n");
+}
+
+#[test]
+fn unclosed_fences_are_warnings() {
+    let input = "Text\n\n  ```\n|a|\n|-|\n";
+    let formatted = format_content(input, &FormatOptions::new().strict(true)).unwrap();
+    assert_eq!(formatted.content, input);
+    assert_eq!(formatted.diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>(),
+               ["3:3: warning: The code block is never closed, the rest of the document is treated as code"]);
+    assert!(format_content(input, &FormatOptions::default()).unwrap().diagnostics.is_empty());
+}
+
+#[test]
+fn blockquote_markers_are_normalized() {
+    let input = ">quote\nlazy\n>  > nested\n> \n>text\n";
+    assert_eq!(format(input, &FormatOptions::default()), ">quote\nlazy\n>  > nested\n>\n>text\n");
+    assert_eq!(format(input, &FormatOptions::new().blockquote_style(BlockquoteStyle::Spaced)), "> quote\n> lazy\n> > nested\n>\n> text\n");
+    assert_eq!(format(input, &FormatOptions::new().blockquote_style(BlockquoteStyle::Compact)), "> quote\n> lazy\n>> nested\n>\n> text\n");
+    assert_eq!(format(">|a|b|\n>|-|-|\n", &FormatOptions::new().blockquote_style(BlockquoteStyle::Spaced)), "> | a | b |\n> |---|---|\n");
+}
+
+#[test]
+fn tables_in_blockquotes() {
+    let options = FormatOptions::default();
+    assert_eq!(format("> |a|b|\n> |-|-|\n> |1|2|\n", &options), "> | a | b |\n> |---|---|\n> | 1 | 2 |\n");
+    assert_eq!(format("> > |a|b|\n> > |-|-|\n> > |1|2|\n", &options), "> > | a | b |\n> > |---|---|\n> > | 1 | 2 |\n");
+
+    let formatted = format_content(">> |a|b|\n>> |-|-|\n>> |1|2|3|\n", &FormatOptions::new().strict(true)).unwrap();
+    assert_eq!(formatted.content, ">> | a | b |\n>> |---|---|\n>> | 1 | 2 |\n");
+    assert_eq!(formatted.diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>(),
+               ["3:4: warning: The row has 3 cells, the ones after the 2 of the header are dropped"]);
+}
+
+#[test]
+fn ignored_blocks() {
+    let options = FormatOptions::default();
+    assert_eq!(format("<!-- mdfmt:ignore -->\n|a|b|\n|-|-|\n\n|c|\n|-|\n", &options), "<!-- mdfmt:ignore -->\n|a|b|\n|-|-|\n\n| c |\n|---|\n");
+    let input = "<!-- mdfmt:off -->\n|a|b|\n|-|-|\n\n*  x\n<!-- mdfmt:on -->\n|c|\n|-|\n";
+    assert_eq!(format(input, &options), "<!-- mdfmt:off -->\n|a|b|\n|-|-|\n\n*  x\n<!-- mdfmt:on -->\n| c |\n|---|\n");
+}
+
+#[test]
+fn disabled_regions() {
+    let options = FormatOptions::default();
+    let input = "|a|\n|-|\n\n<!-- mdfmt:off -->\n|b|\n|-|\n<!-- mdfmt:on -->\n\n|c|\n|-|\n";
+    assert_eq!(format(input, &options), "| a |\n|---|\n\n<!-- mdfmt:off -->\n|b|\n|-|\n<!-- mdfmt:on -->\n\n| c |\n|---|\n");
+    // Without an on marker, the rest of the document is left alone
+    let input = "|a|\n|-|\n<!-- mdfmt:off -->\n|b|\n|-|\n\n\n\n* x\n";
+    assert_eq!(format(input, &options), "| a |\n|---|\n<!-- mdfmt:off -->\n|b|\n|-|\n\n\n\n* x\n");
+}
+
+#[test]
+fn html_blocks_are_verbatim() {
+    let options = FormatOptions::default();
+    let input = "<table>\n<tr><td>|a|b|</td></tr>\n|-|-|\n</table>\n\n|c|\n|-|\n";
+    assert_eq!(format(input, &options), "<table>\n<tr><td>|a|b|</td></tr>\n|-|-|\n</table>\n\n| c |\n|---|\n");
+    // The block ends at a blank line
+    assert_eq!(format("<div>\n|a|b|\n|-|-|\n\n|c|\n|-|\n", &options), "<div>\n|a|b|\n|-|-|\n\n| c |\n|---|\n");
+}
+
+#[test]
+fn fences_in_list_items_keep_their_indentation() {
+    let input = "- item\n\n    ```\n    |a|b|\n    |-|-|\n    ```\n- next\n  ```md\n  *  x\n  ```\n\n|a|b|\n|-|-|\n";
+    let bullets = FormatOptions::new().bullet_char(Some(BulletChar::Asterisk));
+    // The indented fences are closed, so the table after them is formatted
+    assert_eq!(format(input, &bullets), "* item\n\n    ```\n    |a|b|\n    |-|-|\n    ```\n* next\n  ```md\n  *  x\n  ```\n\n| a | b |\n|---|---|\n");
+}
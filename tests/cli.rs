@@ -0,0 +1,473 @@
+//! Runs the `mdfmt` binary on files in temporary directories
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::io::Write;
+
+/// A directory that is removed with its files once the test is done
+struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates an empty directory, named after the test so the tests running at the same time don't share it
+    fn new(test: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("mdfmt-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn write(&self, name: &str, content: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.0.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn read(dir: &TempDir, name: &str) -> String {
+    fs::read_to_string(dir.0.join(name)).unwrap()
+}
+
+/// Runs mdfmt in `dir` with `args`, and `stdin` as its input
+fn mdfmt(dir: &Path, args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_mdfmt"))
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+const UNFORMATTED: &str = "|a|b|\n|-|-|\n|1|2|\n";
+const FORMATTED: &str = "| a | b |\n|---|---|\n| 1 | 2 |\n";
+
+#[test]
+fn no_fail_fast_reports_every_file() {
+    let dir = TempDir::new("no-fail-fast");
+    dir.write("1.md", UNFORMATTED);
+    dir.write("2.md", b"\xff\n");
+    dir.write("3.md", UNFORMATTED);
+    for mode in &[None, Some("--no-fail-fast")] {
+        let mut args = vec!["--list-changed", "--jobs", "1", "1.md", "2.md", "3.md"];
+        args.extend(mode);
+        let output = mdfmt(&dir.0, &args, "");
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(stdout(&output), "1.md\n3.md\n");
+        assert_eq!(stderr(&output), "2.md: 1:1: The input is not valid UTF-8\n");
+    }
+}
+
+#[test]
+fn fail_fast_stops_at_the_first_failure() {
+    let dir = TempDir::new("fail-fast");
+    dir.write("1.md", UNFORMATTED);
+    dir.write("2.md", b"\xff\n");
+    dir.write("3.md", UNFORMATTED);
+    let output = mdfmt(&dir.0, &["--list-changed", "--fail-fast", "--jobs", "1", "1.md", "2.md", "3.md"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "1.md\n");
+    assert_eq!(stderr(&output), "2.md: 1:1: The input is not valid UTF-8\n");
+}
+
+#[test]
+fn fail_fast_reports_a_failure_after_slower_files() {
+    // The invalid file fails while the big ones are still being formatted, which must not skip them or the failure
+    let dir = TempDir::new("fail-fast-parallel");
+    let big = UNFORMATTED.repeat(5000);
+    let mut args = vec!["--list-changed", "--fail-fast", "--jobs", "4"];
+    let names = (0..3).map(|i| format!("{}.md", i)).collect::<Vec<_>>();
+    for name in &names {
+        dir.write(name, &big);
+        args.push(name);
+    }
+    dir.write("bad.md", b"\xff\n");
+    args.push("bad.md");
+    let output = mdfmt(&dir.0, &args, "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "0.md\n1.md\n2.md\n");
+    assert_eq!(stderr(&output), "bad.md: 1:1: The input is not valid UTF-8\n");
+}
+
+#[test]
+fn exit_codes() {
+    let dir = TempDir::new("exit-codes");
+    dir.write("clean.md", FORMATTED);
+    dir.write("dirty.md", UNFORMATTED);
+    assert_eq!(mdfmt(&dir.0, &["--list-changed", "clean.md"], "").status.code(), Some(0));
+    assert_eq!(mdfmt(&dir.0, &["--list-changed", "dirty.md"], "").status.code(), Some(1));
+    assert_eq!(mdfmt(&dir.0, &["--list-changed", "missing.md"], "").status.code(), Some(2));
+    // Invalid arguments aren't files that would change
+    assert_eq!(mdfmt(&dir.0, &["check", "--stream", "dirty.md"], "").status.code(), Some(2));
+    assert_eq!(mdfmt(&dir.0, &["--stream", "--diff", "dirty.md"], "").status.code(), Some(2));
+    assert_eq!(mdfmt(&dir.0, &["--unknown-flag", "dirty.md"], "").status.code(), Some(2));
+    assert_eq!(mdfmt(&dir.0, &["--help"], "").status.code(), Some(0));
+    assert_eq!(mdfmt(&dir.0, &["--version"], "").status.code(), Some(0));
+}
+
+#[test]
+fn list_changed() {
+    let dir = TempDir::new("list-changed");
+    dir.write("clean.md", FORMATTED);
+    dir.write("dirty.md", UNFORMATTED);
+    let output = mdfmt(&dir.0, &["--list-changed", "clean.md", "dirty.md"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "dirty.md\n");
+
+    let output = mdfmt(&dir.0, &["--list-changed", "-"], UNFORMATTED);
+    assert_eq!(stdout(&output), "<stdin>\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn list_changed_prints_paths_as_they_are() {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = TempDir::new("list-changed-bytes");
+    fs::write(dir.0.join(std::ffi::OsStr::from_bytes(b"caf\xe9.md")), UNFORMATTED).unwrap();
+    let output = mdfmt(&dir.0, &["--list-changed", "*.md"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"caf\xe9.md\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn print0_separates_raw_paths_with_nul() {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = TempDir::new("print0");
+    dir.write("a\nb.md", UNFORMATTED);
+    dir.write("clean.md", FORMATTED);
+    fs::write(dir.0.join(std::ffi::OsStr::from_bytes(b"caf\xe9.md")), UNFORMATTED).unwrap();
+    let output = mdfmt(&dir.0, &["--list-changed", "--print0", "*.md"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"a\nb.md\0caf\xe9.md\0");
+
+    let output = mdfmt(&dir.0, &["check", "--print0", "*.md"], "");
+    assert_eq!(output.stdout, b"a\nb.md\0caf\xe9.md\0");
+}
+
+#[test]
+fn closed_stdout_exits_quietly() {
+    let dir = TempDir::new("closed-stdout");
+    dir.write("big.md", UNFORMATTED.repeat(20_000));
+    for args in [&["big.md"][..], &["--stream", "big.md"]] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_mdfmt"))
+            .args(args)
+            .current_dir(&dir.0)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // More is printed than the pipe can hold, so mdfmt is still writing when it's closed
+        drop(child.stdout.take());
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(stderr(&output), "");
+        assert_eq!(output.status.code(), Some(0));
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn stdout_write_errors_are_failures() {
+    let dir = TempDir::new("full-stdout");
+    dir.write("a.md", UNFORMATTED);
+    let output = Command::new(env!("CARGO_BIN_EXE_mdfmt"))
+        .arg("a.md")
+        .current_dir(&dir.0)
+        .stdout(fs::File::create("/dev/full").unwrap())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("Failed printing to stdout: "), "{}", stderr(&output));
+}
+
+#[test]
+fn multiple_files_in_place() {
+    let dir = TempDir::new("multiple-in-place");
+    dir.write("a.md", UNFORMATTED);
+    dir.write("b.md", FORMATTED);
+    dir.write("c.md", UNFORMATTED);
+    let output = mdfmt(&dir.0, &["-i", "a.md", "b.md", "c.md"], "");
+    assert_eq!(output.status.code(), Some(0));
+    for name in ["a.md", "b.md", "c.md"] {
+        assert_eq!(read(&dir, name), FORMATTED);
+    }
+    // Without -i only one file can be formatted, to stdout
+    assert_eq!(mdfmt(&dir.0, &["a.md", "b.md", "c.md"], "").status.code(), Some(2));
+}
+
+#[test]
+fn recursive_directories() {
+    let dir = TempDir::new("recursive");
+    dir.write("docs/a.md", UNFORMATTED);
+    dir.write("docs/notes.txt", UNFORMATTED);
+    dir.write("docs/guide/b.markdown", UNFORMATTED);
+    dir.write("docs/guide/deep/c.md", UNFORMATTED);
+
+    let output = mdfmt(&dir.0, &["--list-changed", "docs"], "");
+    assert_eq!(stdout(&output), "docs/a.md\n");
+    let output = mdfmt(&dir.0, &["--list-changed", "-r", "docs"], "");
+    assert_eq!(stdout(&output), "docs/a.md\ndocs/guide/b.markdown\ndocs/guide/deep/c.md\n");
+
+    assert_eq!(mdfmt(&dir.0, &["-i", "-r", "docs"], "").status.code(), Some(0));
+    assert_eq!(read(&dir, "docs/guide/deep/c.md"), FORMATTED);
+    assert_eq!(read(&dir, "docs/notes.txt"), UNFORMATTED);
+}
+
+#[test]
+fn stdin_filename_is_used_in_warnings() {
+    let dir = TempDir::new("stdin-filename");
+    let output = mdfmt(&dir.0, &["--strict", "--stdin-filename", "docs/x.md"], "|a|b|\n|-|-|\n|1|2|3|\n");
+    assert_eq!(stdout(&output), "| a | b |\n|---|---|\n| 1 | 2 |\n");
+    assert_eq!(stderr(&output), "docs/x.md:3:1: warning: The row has 3 cells, the ones after the 2 of the header are dropped\n");
+
+    let output = mdfmt(&dir.0, &["--strict"], "|a|b|\n|-|-|\n|1|2|3|\n");
+    assert!(stderr(&output).starts_with("<stdin>:3:1: "), "{}", stderr(&output));
+}
+
+#[test]
+fn nearest_config_file_is_used() {
+    let dir = TempDir::new("config-file");
+    dir.write(".mdfmt.toml", "bullet_char = \"*\"\n");
+    dir.write("sub/.mdfmt.toml", "bullet_char = \"+\"\n");
+    dir.write("a.md", "- a\n");
+    dir.write("sub/b.md", "- b\n");
+    dir.write("sub/deeper/c.md", "- c\n");
+    assert_eq!(mdfmt(&dir.0, &["-i", "a.md", "sub/b.md", "sub/deeper/c.md"], "").status.code(), Some(0));
+    assert_eq!(read(&dir, "a.md"), "* a\n");
+    assert_eq!(read(&dir, "sub/b.md"), "+ b\n");
+    assert_eq!(read(&dir, "sub/deeper/c.md"), "+ c\n");
+    // Flags take precedence
+    assert_eq!(stdout(&mdfmt(&dir.0, &["--bullet", "-", "a.md"], "")), "- a\n");
+}
+
+#[test]
+fn explicit_config_file() {
+    let dir = TempDir::new("explicit-config");
+    dir.write(".mdfmt.toml", "bullet_char = \"+\"\n");
+    dir.write("profiles/asterisks.toml", "bullet_char = \"*\"\n");
+    dir.write("bad.toml", "bullet_char = \"?\"\n");
+    dir.write("a.md", "- a\n");
+    // It replaces the config file that would be found, and flags still take precedence
+    assert_eq!(stdout(&mdfmt(&dir.0, &["a.md"], "")), "+ a\n");
+    assert_eq!(stdout(&mdfmt(&dir.0, &["--config", "profiles/asterisks.toml", "a.md"], "")), "* a\n");
+    assert_eq!(stdout(&mdfmt(&dir.0, &["--config", "profiles/asterisks.toml", "--bullet", "-", "a.md"], "")), "- a\n");
+
+    let output = mdfmt(&dir.0, &["--config", "missing.toml", "a.md"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).starts_with("missing.toml: "), "{}", stderr(&output));
+    let output = mdfmt(&dir.0, &["--config", "bad.toml", "a.md"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("'?' is not a bullet"), "{}", stderr(&output));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn parallel_runs_match_sequential_ones() {
+    let dir = TempDir::new("parallel");
+    let mut args = vec!["--diff".to_owned(), "--strict".to_owned()];
+    for i in 0..40 {
+        let name = format!("{:02}.md", i);
+        dir.write(&name, if i % 3 == 0 { FORMATTED.to_owned() } else { format!("{}|{}|\n", UNFORMATTED, i) });
+        args.push(name);
+    }
+    let run = |jobs: &str| {
+        let mut args = args.iter().map(String::as_str).collect::<Vec<_>>();
+        args.extend(["--jobs", jobs]);
+        mdfmt(&dir.0, &args, "")
+    };
+    let sequential = run("1");
+    assert_eq!(sequential.status.code(), Some(1));
+    for jobs in ["2", "8"] {
+        let parallel = run(jobs);
+        assert_eq!(parallel.status.code(), sequential.status.code());
+        assert_eq!(stdout(&parallel), stdout(&sequential));
+        assert_eq!(stderr(&parallel), stderr(&sequential));
+    }
+}
+
+#[test]
+fn file_list_from_stdin() {
+    let dir = TempDir::new("files-from");
+    dir.write("a.md", UNFORMATTED);
+    dir.write("with space.md", UNFORMATTED);
+    dir.write("clean.md", FORMATTED);
+    let output = mdfmt(&dir.0, &["--list-changed", "--files-from", "-"], "a.md\r\n\nwith space.md\nclean.md\n");
+    assert_eq!(stdout(&output), "a.md\nwith space.md\n");
+
+    let output = mdfmt(&dir.0, &["-i", "--files-from0", "-"], "a.md\0with space.md\0");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(read(&dir, "with space.md"), FORMATTED);
+}
+
+#[test]
+fn output_dir() {
+    let dir = TempDir::new("output-dir");
+    dir.write("a.md", UNFORMATTED);
+    dir.write("docs/b.md", UNFORMATTED);
+    let output = mdfmt(&dir.0, &["--output-dir", "out", "a.md", "docs/b.md"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(read(&dir, "out/a.md"), FORMATTED);
+    assert_eq!(read(&dir, "out/docs/b.md"), FORMATTED);
+    // The inputs are left as they are
+    assert_eq!(read(&dir, "a.md"), UNFORMATTED);
+}
+
+#[test]
+fn quiet_hides_warnings() {
+    let dir = TempDir::new("quiet");
+    let input = "|a|b|\n|-|-|\n|1|2|3|\n";
+    assert!(!stderr(&mdfmt(&dir.0, &["--strict"], input)).is_empty());
+    let output = mdfmt(&dir.0, &["--strict", "--quiet"], input);
+    assert_eq!(stderr(&output), "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "| a | b |\n|---|---|\n| 1 | 2 |\n");
+}
+
+#[test]
+fn non_ascii_file_names_in_place() {
+    let dir = TempDir::new("non-ascii-names");
+    dir.write("résumé/日本語.md", UNFORMATTED);
+    assert_eq!(mdfmt(&dir.0, &["-i", "résumé/日本語.md"], "").status.code(), Some(0));
+    assert_eq!(read(&dir, "résumé/日本語.md"), FORMATTED);
+}
+
+#[cfg(windows)]
+#[test]
+fn verbatim_paths_in_place() {
+    let dir = TempDir::new("verbatim-paths");
+    let path = dir.write("é.md", UNFORMATTED);
+    // Like `\\?\C:\Users\...\é.md`, which is also how UNC paths are written
+    let verbatim = fs::canonicalize(&path).unwrap();
+    assert!(verbatim.to_str().unwrap().starts_with(r"\\?\"));
+    assert_eq!(mdfmt(&dir.0, &["-i", verbatim.to_str().unwrap()], "").status.code(), Some(0));
+    assert_eq!(read(&dir, "é.md"), FORMATTED);
+}
+
+#[test]
+fn backups_keep_the_original() {
+    let dir = TempDir::new("backup");
+    dir.write("a.md", UNFORMATTED);
+    assert_eq!(mdfmt(&dir.0, &["-i", "--backup", "a.md"], "").status.code(), Some(0));
+    assert_eq!(read(&dir, "a.md.bak"), UNFORMATTED);
+    assert_eq!(read(&dir, "a.md"), FORMATTED);
+
+    dir.write("a.md", UNFORMATTED);
+    assert_eq!(mdfmt(&dir.0, &["-i", "--backup=.orig", "a.md"], "").status.code(), Some(0));
+    assert_eq!(read(&dir, "a.md.orig"), UNFORMATTED);
+    // An existing backup isn't overwritten without --force
+    dir.write("a.md", "changed\n");
+    assert_eq!(mdfmt(&dir.0, &["-i", "--backup=.orig", "a.md"], "").status.code(), Some(2));
+    assert_eq!(read(&dir, "a.md.orig"), UNFORMATTED);
+    assert_eq!(mdfmt(&dir.0, &["-i", "--backup=.orig", "--force", "a.md"], "").status.code(), Some(0));
+    assert_eq!(read(&dir, "a.md.orig"), "changed\n");
+}
+
+#[test]
+fn explicit_stdin_and_stdout() {
+    let dir = TempDir::new("explicit-stdio");
+    dir.write("out.md", "");
+    let output = mdfmt(&dir.0, &["--stdin", "--stdout"], UNFORMATTED);
+    assert_eq!(stdout(&output), FORMATTED);
+    // With --stdout a second file is another input instead of the output, and only one can be printed
+    dir.write("a.md", UNFORMATTED);
+    let output = mdfmt(&dir.0, &["--stdout", "a.md", "out.md"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(read(&dir, "out.md"), "");
+    let output = mdfmt(&dir.0, &["a.md", "out.md"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(read(&dir, "out.md"), FORMATTED);
+}
+
+#[test]
+fn only_a_single_input_can_be_followed_by_its_output() {
+    let dir = TempDir::new("output-after-directory");
+    dir.write("docs/x.md", UNFORMATTED);
+    dir.write("README.md", "# Readme\n");
+    for input in &["docs", "docs/*.md"] {
+        let output = mdfmt(&dir.0, &[input, "README.md"], "");
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(stderr(&output), "Cannot format multiple files unless inplace.\n");
+        assert_eq!(read(&dir, "README.md"), "# Readme\n");
+    }
+    assert_eq!(mdfmt(&dir.0, &["-", "out.md"], UNFORMATTED).status.code(), Some(0));
+    assert_eq!(read(&dir, "out.md"), FORMATTED);
+}
+
+#[test]
+fn verbose_prints_each_file() {
+    let dir = TempDir::new("verbose");
+    dir.write("clean.md", FORMATTED);
+    dir.write("dirty.md", UNFORMATTED);
+    let output = mdfmt(&dir.0, &["-i", "-v", "clean.md", "dirty.md", "missing.md"], "");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = stderr(&output);
+    let lines = stderr.lines().collect::<Vec<_>>();
+    assert_eq!(lines[..3], ["unchanged clean.md", "formatted dirty.md", "skipped missing.md"]);
+    assert!(lines[3].starts_with("missing.md: "), "{}", stderr);
+    assert_eq!(lines.len(), 4);
+}
+
+#[test]
+fn subcommands() {
+    let dir = TempDir::new("subcommands");
+    dir.write("a.md", UNFORMATTED);
+    let output = mdfmt(&dir.0, &["fmt", "a.md"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), FORMATTED);
+    assert_eq!(stdout(&mdfmt(&dir.0, &["a.md"], "")), FORMATTED);
+
+    let output = mdfmt(&dir.0, &["check", "a.md"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "a.md\n");
+
+    let output = mdfmt(&dir.0, &["diff", "a.md"], "");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "--- a.md\n+++ a.md\n@@ -1,3 +1,3 @@\n-|a|b|\n-|-|-|\n-|1|2|\n+| a | b |\n+|---|---|\n+| 1 | 2 |\n");
+
+    // The shared options are accepted by every subcommand
+    dir.write("mdfmt.toml", "bullet_char = \"*\"\n");
+    dir.write("list.md", "- x\n");
+    let config = ["--config", "mdfmt.toml", "list.md"];
+    assert_eq!(stdout(&mdfmt(&dir.0, &[&["fmt"][..], &config].concat(), "")), "* x\n");
+    assert_eq!(stdout(&mdfmt(&dir.0, &[&["check"][..], &config].concat(), "")), "list.md\n");
+    assert!(stdout(&mdfmt(&dir.0, &[&["diff"][..], &config].concat(), "")).ends_with("-- x\n+* x\n"));
+
+    assert_eq!(mdfmt(&dir.0, &["fmt", "-i", "a.md"], "").status.code(), Some(0));
+    assert_eq!(read(&dir, "a.md"), FORMATTED);
+    assert_eq!(mdfmt(&dir.0, &["check", "a.md"], "").status.code(), Some(0));
+    assert_eq!(stdout(&mdfmt(&dir.0, &["diff", "a.md"], "")), "");
+}
+
+#[test]
+fn summary_counts_each_result() {
+    let dir = TempDir::new("summary");
+    dir.write("1.md", UNFORMATTED);
+    dir.write("2.md", UNFORMATTED);
+    dir.write("3.md", FORMATTED);
+    dir.write("4.md", b"\xff\n");
+    let output = mdfmt(&dir.0, &["--summary", "-i", "--jobs", "1", "1.md", "2.md", "3.md", "4.md"], "");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stderr(&output), "4.md: 1:1: The input is not valid UTF-8\n2 files formatted, 1 unchanged, 1 error\n");
+
+    let output = mdfmt(&dir.0, &["--summary", "--list-changed", "1.md"], "");
+    assert_eq!(stderr(&output), "0 files formatted, 1 unchanged, 0 errors\n");
+    assert_eq!(stderr(&mdfmt(&dir.0, &["--summary", "--quiet", "-i", "1.md"], "")), "");
+}
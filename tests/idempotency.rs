@@ -0,0 +1,95 @@
+//! Formatting a document that was already formatted with the same options never changes it again
+
+use mdfmt::{format_content, BlockquoteStyle, BulletChar, EmphasisChar, FinalNewline, FormatOptions, HeadingStyle, IndentStyle, OrderedListStyle, PipeStyle};
+
+/// Blocks the documents are made of, including broken and ambiguous ones
+const BLOCKS: &[&str] = &[
+    "|a|b|\n|-|-|\n|1|2|",
+    "| Column A | Column B |\n| --- |:-:|\n| Apple | Giant Octopus |\n| Pear | Pointlessly long item |",
+    "a|b\n-|-\n1|2|3",
+    "|a|b|\n|:-|--:|\n|`x|y`|\\|\n|é\u{301}|\u{1F1EB}\u{1F1F7}|",
+    "|a|\n|-|",
+    "|a|b|\n|-|",
+    "| n |\n|---|\n| 10 |\n| 9.5 |\n| -3 |",
+    "Title\n=====",
+    "Section\n---",
+    "#Heading ##",
+    "### Deeper",
+    "Some text that is long enough to be wrapped at a small print width, with *emphasis* and __strong__ words.",
+    "A paragraph  \nwith a hard break\\\nand more",
+    "- a\n* b\n+ c\n  - nested\n\tmore text",
+    "3. one\n1. two\n   5. nested\n9. three",
+    "> quote\nlazy\n>> nested\n> |a|b|\n> |-|-|",
+    "```rust\n|a|b|\n|-|-|\n```",
+    "~~~~\n```\n~~~~",
+    "    indented |a|\n    |-|",
+    "<div>\n|a|\n|-|\n</div>",
+    "<!-- mdfmt:ignore -->\n|a|b|\n|-|-|",
+    "<!-- mdfmt:off -->\n*  x\n<!-- mdfmt:on -->",
+    "[Label]:   http://example.com   \"Title\"",
+    "Text[^2] and[^1]\n\n[^2]: Second\n    continued\n[^1]: First",
+    "***",
+    "- - -",
+    "Table: Caption\n\n|a|\n|-|",
+    "snake_case_name and 2*3*4",
+    "\t \t",
+    "```\nunclosed",
+];
+
+/// Builds documents from a few blocks each, with a xorshift generator so the runs are reproducible
+fn documents() -> Vec<String> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut next = move |bound: usize| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % bound as u64) as usize
+    };
+    let separators = ["\n", "\n\n", "\n\n\n\n", "\r\n"];
+    let mut documents = BLOCKS.iter().map(|block| block.to_string()).collect::<Vec<_>>();
+    for _ in 0..500 {
+        let mut document = String::new();
+        for _ in 0..1 + next(6) {
+            document.push_str(BLOCKS[next(BLOCKS.len())]);
+            document.push_str(separators[next(separators.len())]);
+        }
+        if next(4) == 0 {
+            document.pop();
+        }
+        documents.push(document);
+    }
+    documents
+}
+
+fn options() -> Vec<FormatOptions> {
+    vec![
+        FormatOptions::default(),
+        FormatOptions::new().strict(true).allow_loose_pipes(true).auto_number_align(true).align_decimal(true),
+        FormatOptions::new().compact(true).fit_delimiter_width(false),
+        FormatOptions::new().max_col_width(Some(4)).min_col_width(3).justify_cells(true),
+        FormatOptions::new().print_width(Some(20)).heading_style(HeadingStyle::Atx).fix_heading_space(true),
+        FormatOptions::new().heading_style(HeadingStyle::Setext).atx_closed_headings(false),
+        FormatOptions::new().bullet_char(Some(BulletChar::Asterisk)).ordered_list_style(OrderedListStyle::Sequential)
+            .emphasis_char(Some(EmphasisChar::Underscore)).strong_char(Some(EmphasisChar::Asterisk)),
+        FormatOptions::new().ordered_list_style(OrderedListStyle::Lazy).indent_style(IndentStyle::Spaces),
+        FormatOptions::new().blockquote_style(BlockquoteStyle::Compact).indent_style(IndentStyle::Tabs),
+        FormatOptions::new().blockquote_style(BlockquoteStyle::Spaced).final_newline(FinalNewline::Ensure),
+        FormatOptions::new().pipe_style(PipeStyle::TightOuter).outer_pipes(false).final_newline(FinalNewline::Strip),
+        FormatOptions::new().pipe_style(PipeStyle::TightInner).normalize_code_fence(Some(4)).thematic_break(Some("* * *".parse().unwrap())),
+        FormatOptions::new().normalize_link_definitions(true).lowercase_link_labels(true).renumber_footnotes(true)
+            .normalize_table_captions(true).max_blank_lines(Some(2)),
+        FormatOptions::new().format_tables(false).trim_trailing_whitespace(false).bullet_char(None),
+    ]
+}
+
+#[test]
+fn formatting_twice_changes_nothing() {
+    let documents = documents();
+    for (i, options) in options().iter().enumerate() {
+        for document in &documents {
+            let once = format_content(document, options).unwrap().content;
+            let twice = format_content(&once, options).unwrap().content;
+            assert_eq!(twice, once, "options #{} are not idempotent on {:?}", i, document);
+        }
+    }
+}
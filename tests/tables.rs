@@ -0,0 +1,421 @@
+//! The formatting of tables: alignment, padding and cell widths
+
+use mdfmt::{format_content, parse_tables, FormatOptions, PipeStyle, SortBy, TableAlignment};
+
+fn format(content: &str, options: &FormatOptions) -> String {
+    format_content(content, options).unwrap().content
+}
+
+#[test]
+fn zero_width_characters_are_kept_and_not_counted() {
+    // A heart with a variation selector, a ZWJ sequence and a zero-width space all take two columns
+    let input = "|a|b|\n|-|-|\n|\u{2764}\u{FE0F}|x|\n|\u{1F469}\u{200D}\u{1F4BB}|y|\n|a\u{200B}b|z|\n";
+    let formatted = format(input, &FormatOptions::default());
+    assert_eq!(formatted, "| a  | b |\n|----|---|\n| \u{2764}\u{FE0F} | x |\n| \u{1F469}\u{200D}\u{1F4BB} | y |\n| a\u{200B}b | z |\n");
+}
+
+#[test]
+fn delimiter_shapes_set_the_alignment() {
+    let input = "|a|b|c|d|\n|---|:---|---:|:---:|\n|1|2|3|4|\n";
+    let tables = parse_tables(input, &FormatOptions::default()).unwrap();
+    let alignments = tables[0].columns.iter().map(|column| column.alignment).collect::<Vec<_>>();
+    assert_eq!(alignments, [TableAlignment::None, TableAlignment::Left, TableAlignment::Right, TableAlignment::Center]);
+    assert_eq!(format(input, &FormatOptions::default()), "| a | b | c | d |\n|---|:--|--:|:-:|\n| 1 | 2 | 3 | 4 |\n");
+}
+
+#[test]
+fn right_aligned_tables_are_formatted() {
+    assert_eq!(format("|a|bb|\n|--:|--:|\n|1|2|\n", &FormatOptions::default()), "| a | bb |\n|--:|---:|\n| 1 | 2  |\n");
+}
+
+#[test]
+fn escaped_pipes_stay_in_their_cell() {
+    let options = FormatOptions::default();
+    assert_eq!(format("|a\\|b|c|\n|-|-|\n|1\\|2|3|\n", &options), "| a\\|b | c |\n|------|---|\n| 1\\|2 | 3 |\n");
+    // An escaped pipe makes the delimiter row invalid, so this isn't a table
+    assert_eq!(format("|a\\|b|c|\n|-\\|-|-|\n|1\\|2|3|\n", &options), "|a\\|b|c|\n|-\\|-|-|\n|1\\|2|3|\n");
+}
+
+#[test]
+fn pipes_in_code_spans_stay_in_their_cell() {
+    let input = "|`a|b`|c|\n|-|-|\n|``x|y``|`p|q` \\| r|\n|1\\|`2|3`|4|\n";
+    assert_eq!(format(input, &FormatOptions::default()), concat!(
+        "| `a|b`    | c          |\n",
+        "|----------|------------|\n",
+        "| ``x|y``  | `p|q` \\| r |\n",
+        "| 1\\|`2|3` | 4          |\n"));
+}
+
+#[test]
+fn tabs_in_cells_are_expanded() {
+    let input = "|a\tb|c|\n|-|-|\n|x  y|\tz|\n";
+    assert_eq!(format(input, &FormatOptions::default()), "| a   b | c |\n|-------|---|\n| x  y  | z |\n");
+    assert_eq!(format(input, &FormatOptions::new().tab_width(2)), "| a b  | c |\n|------|---|\n| x  y | z |\n");
+}
+
+#[test]
+fn empty_columns() {
+    let options = FormatOptions::default();
+    assert_eq!(format("|a||c|\n|-|-|-|\n|1||3|\n", &options), "| a |   | c |\n|---|---|---|\n| 1 |   | 3 |\n");
+    assert_eq!(format("||\n|-|\n", &options), "|   |\n|---|\n");
+    assert_eq!(format("||\n|-|\n", &FormatOptions::new().compact(true)), "||\n|---|\n");
+}
+
+#[test]
+fn loose_pipes() {
+    let loose = FormatOptions::new().allow_loose_pipes(true);
+    assert_eq!(format("a|b\n-|-\n1|2\n", &FormatOptions::default()), "a|b\n-|-\n1|2\n");
+    assert_eq!(format("a|b\n-|-\n1|2\n", &loose), "| a | b |\n|---|---|\n| 1 | 2 |\n");
+    assert_eq!(format("|a|b\n-|-|\n1|2\n", &loose), "| a | b |\n|---|---|\n| 1 | 2 |\n");
+    assert_eq!(format("|a|b|\n|-|-|\n|1|2|\n", &loose), "| a | b |\n|---|---|\n| 1 | 2 |\n");
+}
+
+#[test]
+fn compact_tables_round_trip() {
+    let compact = FormatOptions::new().compact(true);
+    let input = "| a   | bb |\n|:---:|---:|\n| one | 2  |\n";
+    let formatted = format(input, &compact);
+    assert_eq!(formatted, "|a|bb|\n|:-:|--:|\n|one|2|\n");
+    assert_eq!(format(&formatted, &compact), formatted);
+    // It's still a table with the same cells
+    let cells = |content| parse_tables(content, &FormatOptions::default()).unwrap()[0].columns.iter()
+        .map(|column| (column.alignment, column.lines.clone()))
+        .collect::<Vec<_>>();
+    assert_eq!(cells(&formatted), cells(input));
+}
+
+#[test]
+fn wide_cells_are_wrapped() {
+    let options = FormatOptions::new().max_col_width(Some(8));
+    assert_eq!(format("|a|b|\n|-|-|\n|one two three four|x|\n|abcdefghijkl|y|\n", &options), concat!(
+        "| a        | b |\n",
+        "|----------|---|\n",
+        "| one two  | x |\n",
+        "| three    |   |\n",
+        "| four     |   |\n",
+        // Without a space, the cell is broken at the width
+        "| abcdefgh | y |\n",
+        "| ijkl     |   |\n"));
+}
+
+#[test]
+fn narrow_columns_expand_to_the_minimum_width() {
+    let options = FormatOptions::new().min_col_width(5);
+    assert_eq!(format("|a|b|\n|-|-|\n|1|2|\n", &options), "| a     | b     |\n|-------|-------|\n| 1     | 2     |\n");
+}
+
+#[test]
+fn numeric_columns_are_right_aligned() {
+    let options = FormatOptions::new().auto_number_align(true);
+    assert_eq!(format("|n|m|\n|-|-|\n|1|1|\n|250|x|\n|-3.5|2|\n", &options),
+               "| n    | m |\n|-----:|---|\n| 1    | 1 |\n| 250  | x |\n| -3.5 | 2 |\n");
+    // An alignment that was chosen is kept
+    assert_eq!(format("|n|\n|:-|\n|1|\n|250|\n", &options), "| n   |\n|:----|\n| 1   |\n| 250 |\n");
+}
+
+#[test]
+fn tall_tables_keep_every_row() {
+    let mut input = String::from("|id|name|\n|-|-|\n");
+    let mut expected = String::from("| id  | name    |\n|-----|---------|\n");
+    for i in 0..1000 {
+        input.push_str(&format!("|{}|row {}|\n", i, i));
+        expected.push_str(&format!("| {:<3} | {:<7} |\n", i, format!("row {}", i)));
+    }
+    assert_eq!(format(&input, &FormatOptions::default()), expected);
+}
+
+#[test]
+fn table_at_the_end_of_the_file() {
+    let options = FormatOptions::default();
+    assert_eq!(format("Text\n\n|a|b|\n|-|-|\n|1|2|", &options), "Text\n\n| a | b |\n|---|---|\n| 1 | 2 |");
+    assert_eq!(format("Text\n\n|a|b|\n|-|-|\n|1|2|\n", &options), "Text\n\n| a | b |\n|---|---|\n| 1 | 2 |\n");
+    assert_eq!(format("|a|b|\n|-|-|", &options), "| a | b |\n|---|---|");
+}
+
+#[test]
+fn delimiter_widths() {
+    let input = "|a|bbbbb|c|\n|:-:|-|--:|\n|1|2|3|\n";
+    assert_eq!(format(input, &FormatOptions::default()), "| a | bbbbb | c |\n|:-:|-------|--:|\n| 1 | 2     | 3 |\n");
+    assert_eq!(format(input, &FormatOptions::new().fit_delimiter_width(false)), "| a | bbbbb | c |\n|:-:|---|--:|\n| 1 | 2     | 3 |\n");
+}
+
+#[test]
+fn flags_and_combining_accents_line_up() {
+    // The flag is two regional indicators taking two columns, the accent combines with the e before it
+    let input = "|a|b|\n|-|-|\n|\u{1F1EB}\u{1F1F7}|x|\n|e\u{301}te|y|\n|ab|z|\n";
+    assert_eq!(format(input, &FormatOptions::default()),
+               "| a   | b |\n|-----|---|\n| \u{1F1EB}\u{1F1F7}  | x |\n| e\u{301}te | y |\n| ab  | z |\n");
+}
+
+#[test]
+fn byte_order_mark_before_a_table() {
+    let input = "\u{FEFF}|a|b|\n|-|-|\n";
+    assert_eq!(format(input, &FormatOptions::default()), "| a | b |\n|---|---|\n");
+    assert_eq!(format(input, &FormatOptions::new().keep_bom(true)), "\u{FEFF}| a | b |\n|---|---|\n");
+}
+
+#[test]
+fn single_dash_delimiters() {
+    let input = "|a|b|c|d|\n|-|:-|-:|:-:|\n";
+    let tables = parse_tables(input, &FormatOptions::default()).unwrap();
+    let alignments = tables[0].columns.iter().map(|column| column.alignment).collect::<Vec<_>>();
+    assert_eq!(alignments, [TableAlignment::None, TableAlignment::Left, TableAlignment::Right, TableAlignment::Center]);
+    assert_eq!(format(input, &FormatOptions::default()), "| a | b | c | d |\n|---|:--|--:|:-:|\n");
+    // A delimiter needs at least one dash
+    assert_eq!(format("|a|\n|:|\n", &FormatOptions::default()), "|a|\n|:|\n");
+    assert_eq!(format("|a|\n|::|\n", &FormatOptions::default()), "|a|\n|::|\n");
+}
+
+#[test]
+fn full_width_colons_are_not_alignment() {
+    let options = FormatOptions::default();
+    assert_eq!(format("|a|b|\n|\u{FF1A}-|-\u{FF1A}|\n", &options), "|a|b|\n|\u{FF1A}-|-\u{FF1A}|\n");
+    assert_eq!(format("|a\u{FF1A}|b|\n|-|-|\n|\u{FF1A}|x|\n", &options), "| a\u{FF1A} | b |\n|-----|---|\n| \u{FF1A}  | x |\n");
+}
+
+#[test]
+fn cells_are_justified_by_alignment() {
+    let input = "|l|c|r|n|\n|:-|:-:|-:|-|\n|aaaa|bbbb|cccc|dddd|\n|a|b|c|d|\n";
+    assert_eq!(format(input, &FormatOptions::new().justify_cells(true)), concat!(
+        "| l    |  c   |    r | n    |\n",
+        "|:-----|:----:|-----:|------|\n",
+        "| aaaa | bbbb | cccc | dddd |\n",
+        "| a    |  b   |    c | d    |\n"));
+}
+
+#[test]
+fn body_cells_wider_than_the_header_keep_the_alignment() {
+    let input = "|a|b|c|\n|:-|:-:|-:|\n|1|2|3|\n|long cell|wide center|right side|\n";
+    let formatted = format(input, &FormatOptions::default());
+    assert_eq!(formatted, concat!(
+        "| a         | b           | c          |\n",
+        "|:----------|:-----------:|-----------:|\n",
+        "| 1         | 2           | 3          |\n",
+        "| long cell | wide center | right side |\n"));
+    let alignments = parse_tables(&formatted, &FormatOptions::default()).unwrap()[0].columns.iter().map(|column| column.alignment).collect::<Vec<_>>();
+    assert_eq!(alignments, [TableAlignment::Left, TableAlignment::Center, TableAlignment::Right]);
+}
+
+#[test]
+fn tables_can_be_left_alone() {
+    let options = FormatOptions::new().format_tables(false);
+    assert_eq!(format("|  a |b|\n|-|:-|\n| 1 |2|\n\ntext  \t\n", &options), "|  a |b|\n|-|:-|\n| 1 |2|\n\ntext\n");
+}
+
+#[test]
+fn column_width_directives() {
+    let input = "<!-- mdfmt:col-width 0=5 -->\n|a|b|\n|-|-|\n|one two three|four five six seven|\n";
+    assert_eq!(format(input, &FormatOptions::default()), concat!(
+        "<!-- mdfmt:col-width 0=5 -->\n",
+        "| a     | b                   |\n",
+        "|-------|---------------------|\n",
+        "| one   | four five six seven |\n",
+        "| two   |                     |\n",
+        "| three |                     |\n"));
+}
+
+#[test]
+fn br_tags_start_continuation_rows() {
+    let input = "|a|b|c|\n|-|-|-|\n|x<br>longer line|y|z|\n";
+    // The column is as wide as the longest line of the cell, not the whole cell
+    assert_eq!(format(input, &FormatOptions::new().max_col_width(Some(20))), concat!(
+        "| a           | b | c |\n",
+        "|-------------|---|---|\n",
+        "| x<br>       | y | z |\n",
+        "| longer line |   |   |\n"));
+    assert_eq!(format(input, &FormatOptions::default()), "| a           | b | c |\n|-------------|---|---|\n| x<br>longer line | y | z |\n");
+}
+
+#[test]
+fn br_tags_do_not_widen_their_column() {
+    let input = "|a|c|\n|-|-|\n|a<br>bbbbbbbbbb|c|\n|x|yy|\n|d<br/>e<BR>|f|\n";
+    // The other rows are padded to the longest line of the cell, so the next column is as close as it can be
+    let formatted = format(input, &FormatOptions::default());
+    assert_eq!(formatted, "| a          | c  |\n|------------|----|\n| a<br>bbbbbbbbbb | c  |\n| x          | yy |\n| d<br/>e<BR> | f  |\n");
+    assert_eq!(format(&formatted, &FormatOptions::default()), formatted);
+}
+
+#[test]
+fn center_delimiters_are_as_wide_as_their_column() {
+    let inputs = ["|a|bbbbbb|c|\n|:-:|:-:|:---:|\n|xx|y|zzzzz|\n", "|a|b|\n|:-:|-:|\n|long cell|x|\n"];
+    for options in [FormatOptions::default(), FormatOptions::new().justify_cells(true), FormatOptions::new().min_col_width(6)] {
+        for input in inputs {
+            let formatted = format(input, &options);
+            let widths = formatted.lines()
+                .map(|row| row.split('|').map(|cell| cell.chars().count()).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            assert!(widths.iter().all(|row| *row == widths[0]), "{}", formatted);
+        }
+    }
+}
+
+#[test]
+fn spaces_in_cells_are_collapsed() {
+    let input = "|a   b|c|\n|-|-|\n|x  y  z|`p   q`|\n";
+    assert_eq!(format(input, &FormatOptions::new().collapse_cell_spaces(true)), "| a b   | c       |\n|-------|---------|\n| x y z | `p   q` |\n");
+    assert_eq!(format(input, &FormatOptions::default()), "| a   b   | c       |\n|---------|---------|\n| x  y  z | `p   q` |\n");
+}
+
+#[test]
+fn right_to_left_text_is_kept_in_logical_order() {
+    let input = "|\u{627}\u{633}\u{645}|b|\n|-|-|\n|\u{645}\u{631}\u{62D}\u{628}\u{627} \u{628}\u{643}|x|\n";
+    let formatted = format(input, &FormatOptions::default());
+    // The column is as wide as the 8 characters of the longest cell, written in the same order as the input
+    assert_eq!(formatted, "| \u{627}\u{633}\u{645}      | b |\n|----------|---|\n| \u{645}\u{631}\u{62D}\u{628}\u{627} \u{628}\u{643} | x |\n");
+}
+
+#[test]
+fn short_and_long_rows() {
+    let input = "|a|b|c|\n|-|-|-|\n|1|\n|1|2|3|4|\n";
+    let lenient = format_content(input, &FormatOptions::new().strict(true)).unwrap();
+    assert_eq!(lenient.content, "| a | b | c |\n|---|---|---|\n| 1 |   |   |\n| 1 | 2 | 3 |\n");
+    assert_eq!(lenient.diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>(),
+               ["4:1: warning: The row has 4 cells, the ones after the 3 of the header are dropped"]);
+
+    let strict = format_content(input, &FormatOptions::new().strict(true).strict_cell_count(true)).unwrap();
+    assert_eq!(strict.content, input);
+    assert_eq!(strict.diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>(),
+               ["1:1: warning: The table appears broken, it will not be formatted (expected 3 cells in a row, found 1)"]);
+    assert_eq!(format("|a|b|c|\n|-|-|-|\n|1|2|3|4|\n", &FormatOptions::new().strict_cell_count(true)), "|a|b|c|\n|-|-|-|\n|1|2|3|4|\n");
+}
+
+#[test]
+fn indented_tables_keep_their_indentation() {
+    let options = FormatOptions::default();
+    assert_eq!(format("- item\n\n  |a|b|\n  |-|-|\n  |1|2|\n\n  more\n", &options), "- item\n\n  | a | b |\n  |---|---|\n  | 1 | 2 |\n\n  more\n");
+    assert_eq!(format("[^1]: A note\n    |a|\n    |-|\n", &options), "[^1]: A note\n    | a |\n    |---|\n");
+    // Continuation rows too
+    assert_eq!(format("1. item\n\n   |a|\n   |-|\n   |1 22|\n", &FormatOptions::new().max_col_width(Some(3))),
+               "1. item\n\n   | a  |\n   |----|\n   | 1  |\n   | 22 |\n");
+}
+
+#[test]
+fn pipe_styles() {
+    let input = "|a|bb|\n|:-|-:|\n|1|2|\n";
+    let cases = [
+        (PipeStyle::Padded, "| a | bb |\n|:--|---:|\n| 1 | 2  |\n"),
+        (PipeStyle::TightOuter, "|a | bb|\n|:-|--:|\n|1 | 2 |\n"),
+        (PipeStyle::TightInner, "| a|bb |\n|:-|--:|\n| 1|2  |\n"),
+    ];
+    let cells = |content: &str| parse_tables(content, &FormatOptions::default()).unwrap()[0].columns.iter()
+        .map(|column| (column.alignment, column.lines.clone()))
+        .collect::<Vec<_>>();
+    for (style, expected) in cases {
+        let options = FormatOptions::new().pipe_style(style);
+        let formatted = format(input, &options);
+        assert_eq!(formatted, expected);
+        // Still the same table, which is left as it is
+        assert_eq!(cells(&formatted), cells(input));
+        assert_eq!(format(&formatted, &options), formatted);
+    }
+}
+
+#[test]
+fn lone_delimiter_rows_are_left_alone() {
+    let options = FormatOptions::default();
+    for input in ["|---|---|\n", "|---|---|\ntext\n", "|---|---|\n|a|b|\n", "text\n\n|---|---|\n", "text\n|---|---|"] {
+        assert_eq!(format(input, &options), input);
+    }
+}
+
+#[test]
+fn columns_keep_their_order() {
+    let input = "|z|a|m|\n|-|-|-|\n|1|2|3|\n";
+    let tables = parse_tables(input, &FormatOptions::default()).unwrap();
+    let headers = tables[0].columns.iter().map(|column| column.lines[0].as_str()).collect::<Vec<_>>();
+    assert_eq!(headers, ["z", "a", "m"]);
+    assert_eq!(format(input, &FormatOptions::default()), "| z | a | m |\n|---|---|---|\n| 1 | 2 | 3 |\n");
+}
+
+#[test]
+fn rows_are_sorted_by_a_column() {
+    let input = "|name|n|\n|-|-|\n|pear|10|\n|Apple|9|\n|fig|100|\n";
+    let sorted = |sort_by: &str| format(input, &FormatOptions::new().sort_by(Some(sort_by.parse::<SortBy>().unwrap())));
+    assert_eq!(sorted("0"), "| name  | n   |\n|-------|-----|\n| Apple | 9   |\n| fig   | 100 |\n| pear  | 10  |\n");
+    assert_eq!(sorted("1:desc"), "| name  | n   |\n|-------|-----|\n| fig   | 100 |\n| pear  | 10  |\n| Apple | 9   |\n");
+}
+
+#[test]
+fn numeric_columns_are_sorted_by_value() {
+    let input = "|name|n|\n|-|-|\n|pear|10|\n|Apple|9|\n|fig|100|\n";
+    let sorted = |sort_by: &str| format(input, &FormatOptions::new().sort_by(Some(sort_by.parse::<SortBy>().unwrap())));
+    assert_eq!(sorted("1"), "| name  | n   |\n|-------|-----|\n| Apple | 9   |\n| pear  | 10  |\n| fig   | 100 |\n");
+    assert_eq!(sorted("1:numeric"), sorted("1"));
+    assert_eq!(sorted("1:lexical"), "| name  | n   |\n|-------|-----|\n| pear  | 10  |\n| fig   | 100 |\n| Apple | 9   |\n");
+
+    // A column mixing numbers and text is sorted lexically, which lint mode points out
+    let options = FormatOptions::new().sort_by(Some("0".parse().unwrap())).lint_tables(true);
+    let formatted = format_content("| n  |\n|----|\n| 9  |\n| x  |\n| 10 |\n", &options).unwrap();
+    assert_eq!(formatted.content, "| n  |\n|----|\n| 10 |\n| 9  |\n| x  |\n");
+    assert_eq!(formatted.diagnostics[0].message, "The rows were sorted lexically, since the column mixes numbers and text");
+}
+
+#[test]
+fn decimal_points_are_lined_up() {
+    let options = FormatOptions::new().align_decimal(true);
+    let formatted = format("|v|\n|-|\n|1.5|\n|10.25|\n|100|\n", &options);
+    assert_eq!(formatted, "| v      |\n|--------|\n|   1.5  |\n|  10.25 |\n| 100    |\n");
+    assert_eq!(format(&formatted, &options), formatted);
+    // A column that isn't only numbers keeps its alignment
+    let input = "|v|\n|--:|\n|1.5|\n|x|\n|100|\n";
+    assert_eq!(format(input, &options), "| v   |\n|----:|\n| 1.5 |\n| x   |\n| 100 |\n");
+    assert_eq!(format(input, &options.justify_cells(true)), "|   v |\n|----:|\n| 1.5 |\n|   x |\n| 100 |\n");
+}
+
+#[test]
+fn ascii_safe_delimiter_rows() {
+    let input = "|a|b|\n|\u{2014}|:\u{2013}:|\n|1|2|\n";
+    let options = FormatOptions::new().ascii_safe(true).unicode_dashes(true);
+    let formatted = format(input, &options);
+    assert_eq!(formatted, "| a | b |\n|---|---|\n| 1 | 2 |\n");
+    assert!(formatted.lines().nth(1).unwrap().chars().all(|c| c == '-' || c == '|'));
+    assert_eq!(format("|a|b|\n|:-|-:|\n|1|2|\n", &FormatOptions::new().ascii_safe(true)), "| a | b |\n|---|---|\n| 1 | 2 |\n");
+}
+
+#[test]
+fn unicode_dashes_in_delimiter_rows() {
+    let input = "|a|b|\n|\u{2014}|:\u{2013}:|\n|1|2|\n";
+    // Without the option it's not a delimiter row, so this isn't a table
+    assert_eq!(format(input, &FormatOptions::default()), input);
+    let options = FormatOptions::new().unicode_dashes(true);
+    let formatted = format(input, &options);
+    assert_eq!(formatted, "| a | b |\n|---|:-:|\n| 1 | 2 |\n");
+    assert_eq!(format(&formatted, &FormatOptions::default()), formatted);
+}
+
+#[test]
+fn captions_stay_next_to_their_table() {
+    let input = "Table:   Fruits\n|a|b|\n|-|-|\n|1|2|\nTable:Below\n\ntext\n";
+    // The caption below isn't a row of the table
+    assert_eq!(format(input, &FormatOptions::default()), "Table:   Fruits\n| a | b |\n|---|---|\n| 1 | 2 |\nTable:Below\n\ntext\n");
+    let options = FormatOptions::new().normalize_table_captions(true);
+    assert_eq!(format(input, &options), "Table: Fruits\n| a | b |\n|---|---|\n| 1 | 2 |\nTable: Below\n\ntext\n");
+    let input = "Table: Above\n\n|a|b|\n|-|-|\n|1|2|\n\nTable:  Below\n";
+    assert_eq!(format(input, &options), "Table: Above\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\nTable: Below\n");
+}
+
+#[test]
+fn borderless_tables_round_trip() {
+    let input = "|a|bb|c|\n|:-|-:|:-:|\n|1|2|3|\n";
+    // Rows without outer pipes are only read as tables with loose pipes
+    let loose = FormatOptions::new().allow_loose_pipes(true);
+    let cells = |content: &str| parse_tables(content, &loose).unwrap()[0].columns.iter()
+        .map(|column| (column.alignment, column.lines.clone()))
+        .collect::<Vec<_>>();
+    let cases = [
+        (PipeStyle::Padded, "a | bb | c\n:-|---:|:-:\n1 | 2  | 3\n"),
+        (PipeStyle::TightInner, "a|bb|c\n:-|-:|:-:\n1|2 |3\n"),
+    ];
+    for (style, expected) in cases {
+        let options = FormatOptions::new().outer_pipes(false).pipe_style(style);
+        let formatted = format(input, &options);
+        assert_eq!(formatted, expected);
+        assert_eq!(cells(&formatted), cells(input));
+        assert_eq!(format(&formatted, &options), formatted);
+        assert_eq!(format(&formatted, &loose), format(input, &FormatOptions::default()));
+    }
+    // A single column or an empty first cell needs the pipes to stay a table
+    let options = FormatOptions::new().outer_pipes(false);
+    assert_eq!(format("|a|\n|-:|\n|1|\n", &options), "| a |\n|--:|\n| 1 |\n");
+    assert_eq!(format("|a|b|\n|-|-|\n||2|\n", &options), "| a | b |\n|---|---|\n|   | 2 |\n");
+}
@@ -0,0 +1,258 @@
+//! The normalizations of the text around tables: headings, lists, emphasis, whitespace and paragraphs
+
+use mdfmt::{format_content, BulletChar, EmphasisChar, FinalNewline, FormatOptions, HeadingStyle, IndentStyle, LineEnding, OrderedListStyle};
+
+fn format(content: &str, options: &FormatOptions) -> String {
+    format_content(content, options).unwrap().content
+}
+
+#[test]
+fn setext_to_atx() {
+    let options = FormatOptions::new().heading_style(HeadingStyle::Atx);
+    assert_eq!(format("Title\n=====\n\nSection\n---\n", &options), "# Title\n\n## Section\n");
+}
+
+#[test]
+fn atx_to_setext() {
+    let options = FormatOptions::new().heading_style(HeadingStyle::Setext);
+    assert_eq!(format("# Title\n\n## Section\n\n### Deeper\n", &options), "Title\n=====\n\nSection\n-------\n\n### Deeper\n");
+}
+
+#[test]
+fn setext_headings_preserved() {
+    let content = "Title\n=====\n\n## Section\n";
+    assert_eq!(format(content, &FormatOptions::new()), content);
+}
+
+#[test]
+fn dashes_under_a_table_header_are_not_a_heading() {
+    // `a | b` with a delimiter row under it is a table, and a lone `---` under a paragraph is a heading
+    let options = FormatOptions::new().heading_style(HeadingStyle::Atx);
+    assert_eq!(format("|a|b|\n|-|-|\n", &options), "| a | b |\n|---|---|\n");
+    assert_eq!(format("a\n---\n", &options), "## a\n");
+}
+
+#[test]
+fn atx_after_paragraph_stays_atx() {
+    // As a setext heading, the title would be underlined together with the paragraph
+    let options = FormatOptions::new().heading_style(HeadingStyle::Setext);
+    assert_eq!(format("para\n# Title\n", &options), "para\n# Title\n");
+    assert_eq!(format("|a|\n|-|\n# Title\n", &options), "| a |\n|---|\n# Title\n");
+    assert_eq!(format("para\n\n# Title\n", &options), "para\n\nTitle\n=====\n");
+}
+
+#[test]
+fn atx_starting_another_block_stays_atx() {
+    let options = FormatOptions::new().heading_style(HeadingStyle::Setext);
+    for heading in &["# - item", "## 1. x", "# > quote", "# ```", "## ***", "# <div>", "# a | b"] {
+        let content = format!("{}\n", heading);
+        assert_eq!(format(&content, &options), content);
+    }
+}
+
+#[test]
+fn whitespace_line_ends_a_wrapped_paragraph() {
+    // The whitespace-only line is blank, so the paragraph before it keeps its line ending as it does without wrapping
+    for options in &[FormatOptions::new(), FormatOptions::new().print_width(Some(40))] {
+        assert_eq!(format("abc\n\t", options), "abc\n");
+        assert_eq!(format("<div\n\t", options), "<div\n");
+        assert_eq!(format("abc\n \t\ndef\n", options), "abc\n\ndef\n");
+    }
+}
+
+#[test]
+fn crlf_documents_round_trip() {
+    let input = "# T\r\n\r\n|a|b|\r\n|-|-|\r\n|1|2|\r\n\r\ntext\r\n";
+    let formatted = format(input, &FormatOptions::default());
+    assert_eq!(formatted, "# T\r\n\r\n| a | b |\r\n|---|---|\r\n| 1 | 2 |\r\n\r\ntext\r\n");
+    assert_eq!(format(&formatted, &FormatOptions::default()), formatted);
+    // The first line ending is used for the whole document, unless one is chosen
+    assert_eq!(format("a\r\nb\nc\r\n", &FormatOptions::default()), "a\r\nb\r\nc\r\n");
+    assert_eq!(format("a\nb\n", &FormatOptions::new().line_ending(LineEnding::Crlf)), "a\r\nb\r\n");
+}
+
+#[test]
+fn final_newline_is_preserved() {
+    let options = FormatOptions::default();
+    assert_eq!(format("text", &options), "text");
+    assert_eq!(format("text\n", &options), "text\n");
+    assert_eq!(format("|a|\n|-|", &options), "| a |\n|---|");
+    assert_eq!(format("|a|\n|-|\n", &options), "| a |\n|---|\n");
+}
+
+#[test]
+fn trailing_whitespace_is_trimmed() {
+    // Two spaces are a hard line break, and code is left alone
+    let input = "a\t \nb  \nc\n\n```\nd \n```\n";
+    assert_eq!(format(input, &FormatOptions::default()), "a\nb  \nc\n\n```\nd \n```\n");
+    assert_eq!(format(input, &FormatOptions::new().trim_trailing_whitespace(false)), input);
+}
+
+#[test]
+fn blank_lines_are_collapsed() {
+    assert_eq!(format("a\n\n\n\n\nb\n", &FormatOptions::default()), "a\n\nb\n");
+    assert_eq!(format("a\n\n\n\n\nb\n", &FormatOptions::new().max_blank_lines(Some(2))), "a\n\n\nb\n");
+    assert_eq!(format("```\na\n\n\n\nb\n```\n", &FormatOptions::default()), "```\na\n\n\n\nb\n```\n");
+}
+
+#[test]
+fn bullets_are_normalized() {
+    let input = "- a\n* b\n+ c\n  * d\n    + e\n";
+    assert_eq!(format(input, &FormatOptions::default()), "- a\n- b\n- c\n  - d\n    - e\n");
+    assert_eq!(format(input, &FormatOptions::new().bullet_char(Some(BulletChar::Asterisk))), "* a\n* b\n* c\n  * d\n    * e\n");
+    assert_eq!(format(input, &FormatOptions::new().bullet_char(None)), input);
+}
+
+#[test]
+fn markers_in_paragraphs_are_kept() {
+    // An empty item can't interrupt a paragraph, so these are the paragraphs "Foo *" and "Foo +", which `-` would underline
+    for input in ["Foo\n*\n", "Foo\n+\n", "Foo\n    * bar\n", "- a\n  text\n      * b\n"] {
+        assert_eq!(format(input, &FormatOptions::default()), input);
+    }
+    assert_eq!(format("Foo\n-\n", &FormatOptions::new().bullet_char(Some(BulletChar::Asterisk))), "Foo\n-\n");
+    // Items with content do interrupt paragraphs
+    assert_eq!(format("Foo\n* bar\n  * baz\n", &FormatOptions::default()), "Foo\n- bar\n  - baz\n");
+}
+
+#[test]
+fn ordered_lists_are_renumbered() {
+    let input = "3. a\n1. b\n7. c\n   5. d\n   2. e\n9. f\n";
+    assert_eq!(format(input, &FormatOptions::default()), input);
+    // Nested lists are numbered on their own, from their first item
+    assert_eq!(format(input, &FormatOptions::new().ordered_list_style(OrderedListStyle::Sequential)),
+               "3. a\n4. b\n5. c\n   5. d\n   6. e\n6. f\n");
+    assert_eq!(format(input, &FormatOptions::new().ordered_list_style(OrderedListStyle::Lazy)),
+               "3. a\n3. b\n3. c\n   5. d\n   5. e\n3. f\n");
+}
+
+#[test]
+fn atx_headings_are_spaced() {
+    let input = "#Title\n\n##   Extra\n\n### Closed ###\n\n#### Closed #####   \n";
+    assert_eq!(format(input, &FormatOptions::default()), "#Title\n\n## Extra\n\n### Closed ###\n\n#### Closed #####\n");
+    let options = FormatOptions::new().fix_heading_space(true);
+    assert_eq!(format(input, &options), "# Title\n\n## Extra\n\n### Closed ###\n\n#### Closed #####\n");
+    assert_eq!(format(input, &options.atx_closed_headings(false)), "# Title\n\n## Extra\n\n### Closed\n\n#### Closed\n");
+}
+
+#[test]
+fn hashtags_are_not_headings() {
+    // Without a space after the `#`, these lines are paragraphs
+    let input = "#tag text\n\n#123abc is fixed\n\nSome text\n#rust is fun\n";
+    assert_eq!(format(input, &FormatOptions::default()), input);
+    assert_eq!(format(input, &FormatOptions::new().heading_style(HeadingStyle::Setext)), input);
+    // Issue references and lines continuing a paragraph stay text even when adding the missing spaces
+    assert_eq!(format(input, &FormatOptions::new().fix_heading_space(true)), "# tag text\n\n#123abc is fixed\n\nSome text\n#rust is fun\n");
+    assert_eq!(format("#\tTabbed\n#\n", &FormatOptions::default()), "# Tabbed\n#\n");
+}
+
+#[test]
+fn emphasis_markers_are_normalized() {
+    let input = "*a* _b_ **c** __d__ snake_case_name `_code_` 2*3*4\n";
+    let options = FormatOptions::new().emphasis_char(Some(EmphasisChar::Underscore)).strong_char(Some(EmphasisChar::Asterisk));
+    // Underscores inside words and markers in code aren't emphasis, nor are asterisks that can't become underscores
+    assert_eq!(format(input, &options), "_a_ _b_ **c** **d** snake_case_name `_code_` 2*3*4\n");
+    let options = FormatOptions::new().emphasis_char(Some(EmphasisChar::Asterisk)).strong_char(Some(EmphasisChar::Underscore));
+    assert_eq!(format(input, &options), "*a* *b* __c__ __d__ snake_case_name `_code_` 2*3*4\n");
+}
+
+#[test]
+fn long_paragraphs_are_wrapped() {
+    let options = FormatOptions::new().print_width(Some(20));
+    assert_eq!(format("The quick brown fox jumps over the lazy dog and keeps running far away.\n", &options),
+               "The quick brown fox\njumps over the lazy\ndog and keeps\nrunning far away.\n");
+}
+
+#[test]
+fn hard_breaks_are_kept_when_wrapping() {
+    let options = FormatOptions::new().print_width(Some(20));
+    assert_eq!(format("First line of text  \nsecond line that is rather long here\n", &options),
+               "First line of text  \nsecond line that is\nrather long here\n");
+    assert_eq!(format("First line of text\\\nsecond line that is rather long here\n", &options),
+               "First line of text\\\nsecond line that is\nrather long here\n");
+}
+
+#[test]
+fn thematic_breaks_are_normalized() {
+    let options = FormatOptions::new().thematic_break(Some("***".parse().unwrap()));
+    // A setext underline and a delimiter row are dashes too, but aren't breaks
+    assert_eq!(format("Text\n---\n\n---\n\n|a|\n|---|\n\n* * *\n", &options), "Text\n---\n\n***\n\n| a |\n|---|\n\n***\n");
+    assert_eq!(format("- - -\n", &FormatOptions::default()), "- - -\n");
+}
+
+#[test]
+fn carriage_return_ends_the_last_line() {
+    let options = FormatOptions::new().final_newline(FinalNewline::Ensure);
+    assert_eq!(format("```\ncode\r", &options), "```\ncode\n");
+    assert_eq!(format("text\r", &FormatOptions::default()), "text\n");
+}
+
+#[test]
+fn link_definitions_are_normalized() {
+    let options = FormatOptions::new().normalize_link_definitions(true);
+    assert_eq!(format("[Foo]:    http://example.com\n", &options), "[Foo]: http://example.com\n");
+    assert_eq!(format("[Bar Baz]:  <http://x.y>   \"A title\"\n[q]: /u 't'\n", &options), "[Bar Baz]: <http://x.y> \"A title\"\n[q]: /u \"t\"\n");
+    assert_eq!(format("[Foo]:    http://example.com\n", &options.clone().lowercase_link_labels(true)), "[foo]: http://example.com\n");
+    assert_eq!(format("[Foo]:    http://example.com\n", &FormatOptions::default()), "[Foo]:    http://example.com\n");
+}
+
+#[test]
+fn final_newline_modes() {
+    let cases = [
+        (FinalNewline::Ensure, ["a\n", "a\n", "a\n\n"]),
+        (FinalNewline::Strip, ["a", "a", "a"]),
+        (FinalNewline::Preserve, ["a", "a\n", "a\n\n"]),
+    ];
+    for (mode, expected) in cases {
+        let options = FormatOptions::new().final_newline(mode);
+        let formatted = ["a", "a\n", "a\n\n\n"].map(|input| format(input, &options));
+        assert_eq!(formatted, expected, "{:?}", mode);
+    }
+}
+
+#[test]
+fn footnotes_are_renumbered() {
+    let input = "Text[^note] and[^3] and[^1].\n\n[^3]: Third\n    continued here\n\n    second paragraph\n[^1]: One\n[^note]: Named\n";
+    let options = FormatOptions::new().renumber_footnotes(true);
+    // Named footnotes keep their label, and the lines of a definition stay in it
+    assert_eq!(format(input, &options),
+               "Text[^note] and[^1] and[^2].\n\n[^1]: Third\n    continued here\n\n    second paragraph\n[^2]: One\n[^note]: Named\n");
+    assert_eq!(format(input, &FormatOptions::default()), input);
+}
+
+#[test]
+fn nested_list_indentation_is_rewritten() {
+    let spaces = FormatOptions::new().indent_style(IndentStyle::Spaces);
+    // Only the leading whitespace changes, not the tabs in the text or in code blocks
+    let input = "- a\n\t- b\n\t\t- c\n  text\twith tab\n\n```\n\tcode\n```\n";
+    assert_eq!(format(input, &spaces), "- a\n    - b\n        - c\n  text\twith tab\n\n```\n\tcode\n```\n");
+    assert_eq!(format(input, &spaces.clone().tab_width(2)), "- a\n  - b\n    - c\n  text\twith tab\n\n```\n\tcode\n```\n");
+    assert_eq!(format("- a\n  \t- b\n \t  - c\n", &spaces), "- a\n    - b\n      - c\n");
+
+    let tabs = FormatOptions::new().indent_style(IndentStyle::Tabs);
+    let input = "- a\n    - b\n        - c\n\n```\n    code\n```\n";
+    assert_eq!(format(input, &tabs), "- a\n\t- b\n\t\t- c\n\n```\n    code\n```\n");
+    // The spaces left over after the last full tab are kept
+    assert_eq!(format("- a\n  \t- b\n \t  - c\n", &tabs), "- a\n\t- b\n\t  - c\n");
+}
+
+#[test]
+fn empty_and_blank_documents() {
+    let options = FormatOptions::default();
+    assert_eq!(format("", &options), "");
+    assert_eq!(format("\n", &options), "\n");
+    // Trailing blank lines are dropped, and so are the spaces of blank lines
+    assert_eq!(format("\n\n\n", &options), "\n");
+    assert_eq!(format("   ", &options), "");
+    assert_eq!(format("  \n\t\n", &options), "\n");
+    assert_eq!(format(" \r\n\r\n", &options), "\r\n");
+
+    let ensure = FormatOptions::new().final_newline(FinalNewline::Ensure);
+    let strip = FormatOptions::new().final_newline(FinalNewline::Strip);
+    // An empty document stays empty, but a blank one is a single empty line
+    assert_eq!(format("", &ensure), "");
+    for input in ["\n", "   ", "\n\n\n", "  \n\t\n"] {
+        assert_eq!(format(input, &ensure), "\n", "{:?}", input);
+        assert_eq!(format(input, &strip), "", "{:?}", input);
+    }
+    assert_eq!(format("", &strip), "");
+}